@@ -0,0 +1,6621 @@
+//! # secret-santa: Finds a secret santa solution
+//!
+//! This tool accepts an input file describing a set of people who wish to be
+//! each other's secret santas. The hope is that by using this tool, the person
+//! organizing it can do so blind to who each other's secret santas are.
+//!
+//! Finding a secret santa solution imposes a number of constraints:
+//!
+//! 1. No one may be their own secret santa.
+//!
+//! 2. Each person is a secret santa for only _one_ other person.
+//!
+//! 3. Each person is a recipient for only _one_ other person.
+//!
+//! The above rules feel like a given. The next set of rules are more
+//! discretionary.
+//!
+//! 4. Unless `allow_mutual` is set, if X is a secret santa to Y, then Y is NOT
+//!    a secret santa to X.
+//!
+//! It just seemed like little cycles like this wouldn't be fun. There can be
+//! longer cycles though. Groups who enjoy giving to each other may set
+//! `allow_mutual` to drop this rule.
+//!
+//! 5. Optional but we do not permit members of the same household to be each
+//!    other's secret santa.
+//!
+//! 6. Optional the history of secret santas can be used to ensure that whomever
+//!    you got last year or the year before, you won't get them again. (You can't
+//!    go back indefinitely though otherwise there would be no solutions.)
+//!
+//! 7. Optional a minimum cycle length, `min_cycle_length`, forbids shorter
+//!    cycles. Rule 4 already forbids cycles of two, so without `allow_mutual`
+//!    it only matters above three; with it, a minimum of three forbids cycles
+//!    of two again. Each short cycle is forbidden one by one, so it's best
+//!    kept to groups of 15 or fewer; larger ones get a warning.
+//!
+//! 8. Optional a minimum displacement, `min_displacement`, forbids giving to
+//!    anyone within that many places in the order of the people, e.g., 1
+//!    forbids giving to a neighbor in the list.
+//!
+//! # Input Sample
+//!
+//! ```ron
+//! (
+//!     people: [
+//!         (
+//!             name: "John",
+//!             email: "john@email.com",
+//!         ),
+//!         (
+//!             name: "Sean",
+//!             email: "sean@email.com",
+//!         ),
+//!         (
+//!             name: "Shane",
+//!             email: "shane@email.com",
+//!         ),
+//!     ],
+//!     whitelist: [
+//!         (
+//!             giver: "Sean",
+//!             receiver: "Shane",
+//!         ),
+//!     ],
+//!     blacklist: [
+//!         (
+//!             giver: "Shane",
+//!             receiver: "John",
+//!         ),
+//!     ],
+//!     blacklist_sets: [
+//!         [
+//!             "John",
+//!             "Sean",
+//!         ],
+//!     ],
+//!     history: [
+//!         (
+//!             year: 2024,
+//!             exclude_pairs: true,
+//!             pairs: [
+//!                 (
+//!                     giver: "John",
+//!                     receiver: "Shane",
+//!                 ),
+//!                 (
+//!                     giver: "Sean",
+//!                     receiver: "John",
+//!                 ),
+//!                 (
+//!                     giver: "Shane",
+//!                     receiver: "Sean",
+//!                 ),
+//!             ],
+//!         ),
+//!     ],
+//! )
+//! ```
+//!
+use clap::ValueEnum;
+#[cfg(feature = "exec")]
+use cmd_lib::{run_cmd, run_fun};
+use hmac::{Hmac, Mac};
+use log::{debug, warn};
+use rand::{prelude::IteratorRandom, rngs::StdRng, RngCore, SeedableRng};
+use ron::ser::PrettyConfig;
+use satoxid::{
+    constraints::{And, AtLeastK, ExactlyK, Not, Or},
+    Backend, CadicalEncoder, Encoder, Lit, Model, Solver, VarMap, VarType,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fmt::{self, Debug, Write},
+    fs::OpenOptions,
+    hash::Hash,
+    io::{BufRead, BufReader, Read, Write as _},
+    net::TcpStream,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// How each solution found is excluded before finding the next.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Default)]
+pub enum Variety {
+    /// No two solutions share a pair
+    #[default]
+    Exact,
+    /// Solutions may share pairs but aren't the same; many more are found
+    Variations,
+}
+
+/// The SAT solver to draw with. satoxid 0.1 ships only CaDiCaL; another
+/// needs a `DrawBackend` implementation and a variant here.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Default)]
+pub enum SatSolver {
+    /// CaDiCaL, built into satoxid
+    #[default]
+    Cadical,
+}
+
+impl fmt::Display for SatSolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SatSolver::Cadical => write!(f, "CaDiCaL"),
+        }
+    }
+}
+
+/// The most people `min_cycle_length` forbids each short cycle among without
+/// a warning that it may be slow.
+pub const SHORT_CYCLE_PEOPLE: usize = 15;
+
+/// The most solutions to find and choose among by default.
+pub const MAX_SOLUTIONS: usize = 100;
+
+/// How to draw besides the input's rules. It's all a draw needs from the
+/// command line, so a draw can be made without one.
+#[derive(Debug, Clone)]
+pub struct DrawOptions {
+    pub variety: Variety,
+    pub prefer: Option<Preference>,
+    pub with_fallback: bool,
+    /// Find at most this many solutions to choose among.
+    pub max_solutions: usize,
+    /// Fail unless at least this many solutions are found.
+    pub require_count: Option<usize>,
+    /// The only file a draw reads or writes.
+    pub base_encoding: Option<PathBuf>,
+    pub solver: SatSolver,
+}
+
+impl Default for DrawOptions {
+    fn default() -> Self {
+        DrawOptions {
+            variety: Variety::default(),
+            prefer: None,
+            with_fallback: false,
+            max_solutions: MAX_SOLUTIONS,
+            require_count: None,
+            base_encoding: None,
+            solver: SatSolver::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Preference {
+    /// Avoid giving between households of very different sizes
+    BalancedHouseholds,
+    /// Give to people not given to in any year of history, so over the years
+    /// everyone gives to everyone else
+    NovelPairs,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
+pub struct Solution {
+    pub year: u16,
+    /// Ignored when the input has a `history_lookback`.
+    #[serde(default)]
+    pub exclude_pairs: bool,
+    pub pairs: Vec<Pair<String>>,
+    /// Pairs to avoid the year after this one, e.g., ones that didn't work
+    /// out.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub avoid_next: Vec<Pair<String>>,
+}
+
+impl Solution {
+    /// Return what keeps this solution from being a valid assignment: someone
+    /// giving or receiving more than the gifts per person, or giving to
+    /// themselves.
+    fn permutation_problems(&self, gifts: usize) -> Vec<String> {
+        let mut problems = vec![];
+        let mut givers = HashMap::new();
+        let mut receivers = HashMap::new();
+        for pair in &self.pairs {
+            let gives = givers.entry(&pair.giver).or_insert(0);
+            *gives += 1;
+            if *gives == gifts + 1 {
+                problems.push(format!("'{}' gives more than {}", pair.giver, times(gifts)));
+            }
+            let receives = receivers.entry(&pair.receiver).or_insert(0);
+            *receives += 1;
+            if *receives == gifts + 1 {
+                problems.push(format!(
+                    "'{}' receives more than {}",
+                    pair.receiver,
+                    times(gifts)
+                ));
+            }
+            if pair.giver == pair.receiver {
+                problems.push(format!("'{}' gives to themselves", pair.giver));
+            }
+        }
+        problems
+    }
+}
+
+/// Return how many times, e.g., "once" or "2 times".
+fn times(count: usize) -> String {
+    match count {
+        1 => String::from("once"),
+        _ => format!("{count} times"),
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
+pub struct Input {
+    /// May be left out when the people come from `--people`.
+    #[serde(default)]
+    pub people: Vec<Person>,
+    pub whitelist: Vec<Pair<String>>,
+    pub blacklist: Vec<Pair<String>>,
+    /// Allow two people to give to each other, which rule 4 forbids.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub allow_mutual: bool,
+    /// How many people each person gives to and receives from; 1 if not
+    /// given. The rules about cycles need it to be 1.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gifts_per_person: Option<usize>,
+    pub blacklist_sets: Vec<Vec<String>>,
+    /// Couples are a unit for the households and blacklist sets: each
+    /// partner is added to every one of the other's, so neither gives to
+    /// the other's relatives or to each other. Unlike a household, they
+    /// needn't live together, and only the encoding counts them, not, e.g.,
+    /// `--prefer balanced-households`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub couples: Vec<(String, String)>,
+    pub history: Vec<Solution>,
+    /// The command to deliver messages with when `--exec` is not given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exec: Option<String>,
+    /// A giver of honor who gives to a different receiver each year.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub honor: Option<HonorRotation>,
+    /// Pairs the solver tries first. Unlike the whitelist they may be broken.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub encouraged: Vec<Pair<String>>,
+    /// Pairs people would like, or with a negative weight would rather
+    /// avoid, each with its weight in points. A solution earns the points of
+    /// those it includes or, for a negative weight, leaves out, and the one
+    /// earning the most is found. Unlike the whitelist they may be broken.
+    /// Keep the weights small; each point is a literal in the encoding.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub preferences: Vec<(Pair<String>, i32)>,
+    /// Separate exchanges, each drawn only among its own members.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<Group>,
+    /// The rules to draw by.
+    #[serde(default)]
+    pub rules: Rules,
+    /// Named sets of rules; `--profile` picks one to use instead of `rules`.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub profiles: HashMap<String, Rules>,
+    /// List at most this many past receivers and Secret Santas in messages.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub history_message_limit: Option<usize>,
+    /// Leave out the optional sections of a message, the history first,
+    /// then the previous gifts, then the receiver's wishlist, until its body
+    /// is at most this many characters. The assignment itself is always kept.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_body_length: Option<usize>,
+    /// The body of the messages instead of the usual wording, with
+    /// placeholders like `{giver}` and `{receiver}`; see `PLACEHOLDERS`.
+    /// `--template` reads it from a file. `max_body_length` doesn't apply.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+    /// Exclude the pairs of this many of the most recent solutions in history
+    /// instead of those whose `exclude_pairs` is set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub history_lookback: Option<u16>,
+    /// Sets of people who must each be in a different cycle.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub different_cycle_sets: Vec<Vec<String>>,
+    /// When the gifts are exchanged, e.g., "December 20th at 7pm". Every
+    /// message says so.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event_date: Option<String>,
+    /// Where the gifts are exchanged. Every message says so.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event_location: Option<String>,
+    /// How much to spend on a gift, e.g., "$25". Every message says so.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub budget: Option<String>,
+    /// The order of the people for `min_displacement`, if not the order
+    /// they're listed in. Anyone left out may give to anyone.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub order: Vec<String>,
+    /// Who the footer of the messages credits, if not this crate.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branding: Option<Branding>,
+    /// The distance between two people either way, e.g., miles to mail a
+    /// gift. When given, the solutions with the least total are preferred.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub distances: Vec<(String, String, u32)>,
+    /// The distance between two people not in `distances`. The greatest
+    /// distance there if none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub missing_distance: Option<u32>,
+}
+
+/// Rules that can be turned on or off, alone or in a named profile.
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
+pub struct Rules {
+    /// Forbid pairs that would close a short loop of giving with recent
+    /// history.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gift_back: Option<GiftBack>,
+    /// Require every cycle of givers to include someone with each value of
+    /// this attribute.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub balance_attribute: Option<String>,
+    /// Require exactly this many cycles of givers. It's checked among the
+    /// solutions found rather than encoded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exact_cycles: Option<usize>,
+    /// Forbid repeating a whole cycle of givers from this many of the most
+    /// recent years, even those whose pairs aren't excluded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub no_repeat_cycles: Option<usize>,
+    /// Forbid any cycle whose members are all in one household or all share
+    /// an attribute's value. It's checked among the solutions found, so with
+    /// one large household or value there may be none.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub require_cross_group_cycles: bool,
+    /// Prefer solutions that repeat fewer historical pairs. A repeat from the
+    /// latest year counts 1, from the year before `history_decay`, then
+    /// `history_decay²`, and so on.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub history_decay: Option<f32>,
+    /// Forbid giving to someone with the same surname, i.e., the last word of
+    /// their name. A name of one word has no surname.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub forbid_same_surname: bool,
+    /// Forbid cycles of givers with fewer people than this.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_cycle_length: Option<usize>,
+    /// Forbid giving to anyone within this many places in the order of the
+    /// people; 1 forbids giving to a neighbor.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_displacement: Option<usize>,
+    /// Forbid giving to someone in a different country, so no gift is
+    /// mailed abroad. Anyone without a country may give to anyone. With
+    /// people spread across many countries there may be no solution; the
+    /// error then says which rules to relax.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub same_country_only: bool,
+    /// Forbid reversing last year's solution, where everyone gives to whoever
+    /// gave to them. It's checked among the solutions found.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub forbid_reversal: bool,
+    /// With `forbid_reversal`, also forbid solutions that reverse more than
+    /// this many of last year's pairs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_reversed_pairs: Option<usize>,
+}
+
+/// Forbid X giving to Y when, within the last `lookback` years, Y gave to X
+/// through at most `max_length - 1` gifts, e.g., Y gave to Z who gave to X.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+pub struct GiftBack {
+    /// The longest loop to forbid; 2 forbids only giving back directly.
+    pub max_length: usize,
+    pub lookback: usize,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Group {
+    pub label: String,
+    /// Members may instead list the group in their own `groups`.
+    #[serde(default)]
+    pub members: Vec<String>,
+}
+
+/// The giver of honor gives to `receivers[year % receivers.len()]`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct HonorRotation {
+    pub giver: String,
+    pub receivers: Vec<String>,
+}
+
+/// A mistake in the input file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InputError {
+    /// An input that can't be read.
+    Read { path: PathBuf, error: String },
+    /// A people source that can't be read.
+    People(String),
+    /// An input that isn't valid RON or JSON.
+    Parse { path: PathBuf, error: String },
+    /// A giver in history who isn't in the people set.
+    UnknownGiver(String),
+    /// A receiver in history who isn't in the people set.
+    UnknownReceiver(String),
+    /// A giver someone won't receive from who isn't in the people set.
+    MissingExcludedGiver { person: String, giver: String },
+    /// A name shared by more than one person.
+    DuplicateName(String),
+    /// An email address that can't be right.
+    InvalidEmail { person: String, email: String },
+    /// A placeholder in the template that isn't one of `PLACEHOLDERS`.
+    UnknownPlaceholder(String),
+    /// A giver or receiver of honor who isn't in the people set.
+    UnknownHonor(String),
+    /// A giver of honor who'd give to themselves this year.
+    HonorSelf(String),
+    /// A giver of honor's pair this year that's blacklisted.
+    HonorBlacklisted { giver: String, receiver: String },
+    /// A giver of honor's pair this year in the same household.
+    HonorHousehold { giver: String, receiver: String },
+    /// A name in a different cycle set who isn't in the people set.
+    UnknownCycleSetName(String),
+    /// A different cycle set with more people than there can be cycles.
+    CycleSetTooLarge { set: Vec<String>, most: usize },
+    /// A profile that isn't in the profiles.
+    UnknownProfile(String),
+    /// A group a person lists that isn't in the groups.
+    UnknownGroup { person: String, group: String },
+    /// A group member who isn't in the people set.
+    UnknownMember { group: String, member: String },
+    /// A partner in a couple who isn't in the people set.
+    UnknownPartner(String),
+    /// A pair in both the whitelist and blacklist.
+    WhitelistBlacklisted { giver: String, receiver: String },
+    /// A whitelisted pair in the same household or blacklist set.
+    WhitelistHousehold { giver: String, receiver: String },
+    /// Gifts per person of zero.
+    NoGifts,
+    /// A rule about cycles used with more than one gift per person.
+    CycleRuleWithGifts { rule: &'static str, gifts: usize },
+    /// A name in the order who isn't in the people set.
+    UnknownOrderName(String),
+    /// A minimum displacement that leaves someone in a draw no one to give to.
+    MinDisplacement {
+        distance: usize,
+        people: usize,
+        label: Option<String>,
+        most: usize,
+    },
+    /// More exact cycles than the people of a draw can make, or none.
+    ExactCycles {
+        count: usize,
+        people: usize,
+        label: Option<String>,
+        most: usize,
+    },
+}
+
+impl fmt::Display for InputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InputError::Read { path, error } => {
+                write!(f, "Failed reading '{}': {error}", path.display())
+            }
+            InputError::People(error) => write!(f, "Failed reading people: {error}"),
+            InputError::Parse { path, error } => {
+                write!(f, "Failed parsing '{}': {error}", path.display())
+            }
+            InputError::UnknownGiver(name) => write!(
+                f,
+                "Giver named '{name}' present in history but not found in people set."
+            ),
+            InputError::UnknownReceiver(name) => write!(
+                f,
+                "Receiver named '{name}' present in history but not found in people set."
+            ),
+            InputError::MissingExcludedGiver { person, giver } => write!(
+                f,
+                "Giver named '{giver}' excluded by '{person}' but not found in people set."
+            ),
+            InputError::InvalidEmail { person, email } => write!(
+                f,
+                "Email '{email}' of person named '{person}' is not a valid address."
+            ),
+            InputError::UnknownPlaceholder(name) => write!(
+                f,
+                "Placeholder '{{{name}}}' in template is not one of: {}.",
+                PLACEHOLDERS.map(|p| format!("{{{p}}}")).join(", ")
+            ),
+            InputError::UnknownHonor(name) => {
+                write!(f, "Honor named '{name}' not found in people set.")
+            }
+            InputError::HonorSelf(name) => {
+                write!(f, "Giver of honor '{name}' cannot give to themselves.")
+            }
+            InputError::HonorBlacklisted { giver, receiver } => write!(
+                f,
+                "Giver of honor '{giver}' giving to '{receiver}' is blacklisted."
+            ),
+            InputError::HonorHousehold { giver, receiver } => write!(
+                f,
+                "Giver of honor '{giver}' and '{receiver}' are in the same household."
+            ),
+            InputError::UnknownCycleSetName(name) => write!(
+                f,
+                "Named '{name}' in different cycle set but not found in people set."
+            ),
+            InputError::CycleSetTooLarge { set, most } => write!(
+                f,
+                "Different cycle set {set:?} needs {} cycles; at most {most} possible.",
+                set.len()
+            ),
+            InputError::UnknownProfile(name) => {
+                write!(f, "Profile named '{name}' not found in profiles.")
+            }
+            InputError::UnknownGroup { person, group } => write!(
+                f,
+                "Group named '{group}' present in person '{person}' but not found in groups."
+            ),
+            InputError::UnknownMember { group, member } => write!(
+                f,
+                "Member named '{member}' present in group '{group}' but not found in people set."
+            ),
+            InputError::UnknownPartner(name) => write!(
+                f,
+                "Partner named '{name}' present in couples but not found in people set."
+            ),
+            InputError::WhitelistBlacklisted { giver, receiver } => write!(
+                f,
+                "Pair '{giver}' to '{receiver}' present in both whitelist and blacklist."
+            ),
+            InputError::WhitelistHousehold { giver, receiver } => write!(
+                f,
+                "Pair '{giver}' to '{receiver}' whitelisted but in the same household or blacklist set."
+            ),
+            InputError::NoGifts => write!(f, "Gifts per person must be at least 1."),
+            InputError::CycleRuleWithGifts { rule, gifts } => write!(
+                f,
+                "Rule '{rule}' needs gifts per person to be 1, not {gifts}."
+            ),
+            InputError::UnknownOrderName(name) => write!(
+                f,
+                "Named '{name}' in order but not found in people set."
+            ),
+            InputError::MinDisplacement {
+                distance,
+                people,
+                label,
+                most,
+            } => write!(
+                f,
+                "Minimum displacement {distance} not possible with {people} people in order{}; at most {most}.",
+                label.as_ref().map(|l| format!(" in '{l}'")).unwrap_or_default()
+            ),
+            InputError::ExactCycles {
+                count,
+                people,
+                label,
+                most,
+            } => write!(
+                f,
+                "Exact cycles {count} not possible with {people} people{}; at most {most} cycles.",
+                label.as_ref().map(|l| format!(" in '{l}'")).unwrap_or_default()
+            ),
+            InputError::DuplicateName(name) => {
+                write!(
+                    f,
+                    "Person named '{name}' present more than once in people set."
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for InputError {}
+
+impl Input {
+    /// Return the households, i.e., the sets of people who may not be each
+    /// other's secret santa: the blacklist sets merged with the people who
+    /// name the same household.
+    fn households(&self) -> Vec<Vec<String>> {
+        let mut households = self.blacklist_sets.clone();
+        let mut named: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+        for person in &self.people {
+            if let Some(ref household) = person.household {
+                named
+                    .entry(household)
+                    .or_default()
+                    .push(person.name.clone());
+            }
+        }
+        for members in named.into_values() {
+            match households
+                .iter_mut()
+                .find(|set| set.iter().any(|name| members.contains(name)))
+            {
+                Some(set) => {
+                    let new: Vec<String> =
+                        members.into_iter().filter(|n| !set.contains(n)).collect();
+                    set.extend(new);
+                }
+                None => households.push(members),
+            }
+        }
+        households
+    }
+
+    /// Return the households with each partner of a couple added to the
+    /// other's, and a couple in none as a household of their own.
+    fn couple_households(&self) -> Vec<Vec<String>> {
+        let mut households = self.households();
+        for (a, b) in &self.couples {
+            for set in &mut households {
+                match (set.contains(a), set.contains(b)) {
+                    (true, false) => set.push(b.clone()),
+                    (false, true) => set.push(a.clone()),
+                    _ => {}
+                }
+            }
+            if !households.iter().any(|set| set.contains(a)) {
+                households.push(vec![a.clone(), b.clone()]);
+            }
+        }
+        households
+    }
+
+    /// Return the sets of people who share a surname, leaving out charities.
+    fn surname_sets(&self) -> Vec<Vec<String>> {
+        let mut sets: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+        for person in self.people.iter().filter(|p| !p.charity) {
+            if let Some(surname) = surname(&person.name) {
+                sets.entry(surname).or_default().push(person.name.clone());
+            }
+        }
+        sets.into_values().filter(|set| set.len() > 1).collect()
+    }
+
+    /// Return the pairs of people in different countries, leaving out
+    /// charities and anyone without a country.
+    fn cross_country_pairs(&self) -> Vec<Pair<String>> {
+        let people: Vec<(&String, &String)> = self
+            .people
+            .iter()
+            .filter(|p| !p.charity)
+            .filter_map(|p| Some((&p.name, p.country.as_ref()?)))
+            .collect();
+        let mut pairs = vec![];
+        for (giver, from) in &people {
+            for (receiver, to) in &people {
+                if from != to {
+                    pairs.push(Pair::new(giver.to_string(), receiver.to_string()));
+                }
+            }
+        }
+        pairs
+    }
+
+    /// Return the weight of the preferences the pairs satisfy, i.e., those
+    /// with a positive weight it includes and a negative one it doesn't.
+    fn preference_points(&self, pairs: &[Pair<String>]) -> i64 {
+        self.preferences
+            .iter()
+            .map(|(pair, weight)| match (pairs.contains(pair), *weight > 0) {
+                (true, true) => *weight as i64,
+                (false, false) => -*weight as i64,
+                _ => 0,
+            })
+            .sum()
+    }
+
+    /// Return the most points the preferences could earn.
+    fn possible_points(&self) -> i64 {
+        self.preferences
+            .iter()
+            .map(|(_, weight)| weight.unsigned_abs() as i64)
+            .sum()
+    }
+
+    /// Return the total distance from each giver to their receiver.
+    fn total_distance(&self, pairs: &[Pair<String>]) -> u64 {
+        let missing = self
+            .missing_distance
+            .or_else(|| self.distances.iter().map(|(_, _, d)| *d).max())
+            .unwrap_or(0);
+        pairs
+            .iter()
+            .map(|pair| {
+                self.distances
+                    .iter()
+                    .find(|(a, b, _)| {
+                        (a == &pair.giver && b == &pair.receiver)
+                            || (a == &pair.receiver && b == &pair.giver)
+                    })
+                    .map_or(missing, |(_, _, d)| *d) as u64
+            })
+            .sum()
+    }
+
+    /// Return the footer that ends every message.
+    pub fn footer(&self) -> String {
+        self.branding.clone().unwrap_or_default().footer()
+    }
+
+    /// Return how many people each person gives to and receives from.
+    fn gifts_each(&self) -> usize {
+        self.gifts_per_person.unwrap_or(1)
+    }
+
+    /// Confirm the gifts per person are possible and no rule about cycles is
+    /// used with more than one, since the pairs then aren't cycles.
+    fn check_gifts_per_person(&self) -> Result<(), InputError> {
+        let gifts = self.gifts_each();
+        if gifts == 0 {
+            return Err(InputError::NoGifts);
+        }
+        if gifts == 1 {
+            return Ok(());
+        }
+        let rules = &self.rules;
+        let cycle_rules = [
+            ("exact_cycles", rules.exact_cycles.is_some()),
+            ("no_repeat_cycles", rules.no_repeat_cycles.is_some()),
+            ("balance_attribute", rules.balance_attribute.is_some()),
+            ("min_cycle_length", rules.min_cycle_length.is_some()),
+            (
+                "require_cross_group_cycles",
+                rules.require_cross_group_cycles,
+            ),
+            ("forbid_reversal", rules.forbid_reversal),
+            (
+                "different_cycle_sets",
+                !self.different_cycle_sets.is_empty(),
+            ),
+        ];
+        match cycle_rules.iter().find(|(_, used)| *used) {
+            Some((rule, _)) => Err(InputError::CycleRuleWithGifts { rule, gifts }),
+            None => Ok(()),
+        }
+    }
+
+    /// Return the names of the people.
+    fn names(&self) -> Vec<String> {
+        self.people.iter().map(|p| p.name.clone()).collect()
+    }
+
+    /// Return true if the name is a charity.
+    fn is_charity(&self, name: &str) -> bool {
+        self.people.iter().any(|p| p.charity && p.name == name)
+    }
+
+    /// Return the pairs with no charity in them, e.g., to compare with
+    /// history, which charities are exempt from.
+    fn without_charities(&self, pairs: &[Pair<String>]) -> Vec<Pair<String>> {
+        pairs
+            .iter()
+            .filter(|p| !self.is_charity(&p.giver) && !self.is_charity(&p.receiver))
+            .cloned()
+            .collect()
+    }
+
+    /// Return the pairs that are gifts. The solver still gives the charity a
+    /// receiver; that person receives no gift.
+    fn gifts(&self, mut pairs: Vec<Pair<String>>) -> Vec<Pair<String>> {
+        pairs.retain(|pair| !self.is_charity(&pair.giver));
+        pairs
+    }
+
+    /// Return the giver of honor's pair for this year.
+    fn honor_pair(&self, year: u16) -> Option<Pair<String>> {
+        let honor = self.honor.as_ref()?;
+        if honor.receivers.is_empty() {
+            return None;
+        }
+        let receiver = &honor.receivers[year as usize % honor.receivers.len()];
+        Some(Pair::new(honor.giver.clone(), receiver.clone()))
+    }
+
+    /// Apply the constraints that depend on the year.
+    fn for_year(&mut self, year: u16) -> Result<(), InputError> {
+        self.blacklist
+            .retain(|pair| pair.until_year.is_none_or(|until| year <= until));
+        if let Some(pair) = self.honor_pair(year) {
+            self.check_honor(&pair)?;
+            self.whitelist.push(pair);
+        }
+        Ok(())
+    }
+
+    /// Confirm the giver of honor's pair doesn't break any other rule.
+    fn check_honor(&self, pair: &Pair<String>) -> Result<(), InputError> {
+        for name in [&pair.giver, &pair.receiver] {
+            if !self.people.iter().any(|p| &p.name == name) {
+                return Err(InputError::UnknownHonor(name.clone()));
+            }
+        }
+        let giver = pair.giver.clone();
+        let receiver = pair.receiver.clone();
+        if giver == receiver {
+            return Err(InputError::HonorSelf(giver));
+        }
+        if self.blacklist.contains(pair) {
+            return Err(InputError::HonorBlacklisted { giver, receiver });
+        }
+        if self
+            .households()
+            .iter()
+            .any(|h| h.contains(&pair.giver) && h.contains(&pair.receiver))
+        {
+            return Err(InputError::HonorHousehold { giver, receiver });
+        }
+        Ok(())
+    }
+
+    /// Return the draws to make: the whole input, or one per group with its
+    /// label.
+    pub fn draws(&self) -> Vec<(Option<String>, Input)> {
+        if self.groups.is_empty() {
+            return vec![(None, self.clone())];
+        }
+        self.groups
+            .iter()
+            .map(|group| (Some(group.label.clone()), self.group(group)))
+            .collect()
+    }
+
+    /// Return the members of a group: those it lists and those who list it.
+    fn members(&self, group: &Group) -> Vec<String> {
+        let mut members = group.members.clone();
+        for person in &self.people {
+            if person.groups.contains(&group.label) && !members.contains(&person.name) {
+                members.push(person.name.clone());
+            }
+        }
+        members
+    }
+
+    /// Return this input restricted to the members of a group.
+    fn group(&self, group: &Group) -> Input {
+        let members = self.members(group);
+        let member = |name: &String| members.contains(name);
+        let within = |pair: &&Pair<String>| member(&pair.giver) && member(&pair.receiver);
+        Input {
+            people: self
+                .people
+                .iter()
+                .filter(|p| member(&p.name))
+                .cloned()
+                .collect(),
+            whitelist: self.whitelist.iter().filter(within).cloned().collect(),
+            blacklist: self.blacklist.iter().filter(within).cloned().collect(),
+            blacklist_sets: self
+                .blacklist_sets
+                .iter()
+                .map(|set| set.iter().filter(|n| member(n)).cloned().collect())
+                .collect(),
+            history: self
+                .history
+                .iter()
+                .map(|solution| Solution {
+                    pairs: solution.pairs.iter().filter(within).cloned().collect(),
+                    avoid_next: solution.avoid_next.iter().filter(within).cloned().collect(),
+                    ..solution.clone()
+                })
+                .collect(),
+            honor: None,
+            groups: vec![],
+            ..self.clone()
+        }
+    }
+
+    /// Confirm all group members are in the people list and every group a
+    /// person lists exists.
+    fn check_groups(&self) -> Result<(), InputError> {
+        for person in &self.people {
+            for label in &person.groups {
+                if !self.groups.iter().any(|g| &g.label == label) {
+                    return Err(InputError::UnknownGroup {
+                        person: person.name.clone(),
+                        group: label.clone(),
+                    });
+                }
+            }
+        }
+        for group in &self.groups {
+            for name in &group.members {
+                if !self.people.iter().any(|p| &p.name == name) {
+                    return Err(InputError::UnknownMember {
+                        group: group.label.clone(),
+                        member: name.clone(),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Add a person, in a member's household if given.
+    pub fn add_person(
+        &mut self,
+        mut person: Person,
+        household_of: Option<&str>,
+    ) -> Result<(), String> {
+        if self.people.iter().any(|p| p.name == person.name) {
+            return Err(format!(
+                "Person named '{}' already in people set.",
+                person.name
+            ));
+        }
+        if let Some(member) = household_of {
+            let Some(existing) = self.people.iter().find(|p| p.name == member) else {
+                return Err(format!("Member named '{member}' not found in people set."));
+            };
+            if existing.household.is_some() {
+                person.household = existing.household.clone();
+            } else {
+                match self
+                    .blacklist_sets
+                    .iter_mut()
+                    .find(|set| set.iter().any(|name| name == member))
+                {
+                    Some(set) => set.push(person.name.clone()),
+                    None => self
+                        .blacklist_sets
+                        .push(vec![member.to_string(), person.name.clone()]),
+                }
+            }
+        }
+        self.people.push(person);
+        Ok(())
+    }
+
+    /// Use the named profile's rules.
+    fn use_profile(&mut self, name: &str) -> Result<(), InputError> {
+        match self.profiles.get(name) {
+            Some(rules) => self.rules = rules.clone(),
+            None => return Err(InputError::UnknownProfile(name.to_string())),
+        }
+        Ok(())
+    }
+
+    /// Return the names of the people present in their order.
+    fn ordered_names(&self) -> Vec<String> {
+        if self.order.is_empty() {
+            return self.names();
+        }
+        self.order
+            .iter()
+            .filter(|name| self.people.iter().any(|p| &p.name == *name))
+            .cloned()
+            .collect()
+    }
+
+    /// Return the pairs within `distance` places of each other in the order.
+    fn too_close_pairs(&self, distance: usize) -> Vec<Pair<String>> {
+        let order = self.ordered_names();
+        let mut pairs = vec![];
+        for (i, giver) in order.iter().enumerate() {
+            for (j, receiver) in order.iter().enumerate() {
+                if i != j && i.abs_diff(j) <= distance {
+                    pairs.push(Pair::new(giver.clone(), receiver.clone()));
+                }
+            }
+        }
+        pairs
+    }
+
+    /// Confirm the names in the order are people and, with the minimum
+    /// displacement, everyone has someone far enough away to give to.
+    fn check_min_displacement(&self) -> Result<(), InputError> {
+        for name in &self.order {
+            if !self.people.iter().any(|p| &p.name == name) {
+                return Err(InputError::UnknownOrderName(name.clone()));
+            }
+        }
+        let Some(distance) = self.rules.min_displacement else {
+            return Ok(());
+        };
+        for (label, draw) in self.draws() {
+            let count = draw.ordered_names().len();
+            // The person in the middle has the fewest people far enough away.
+            if count < 2 * distance + 2 {
+                return Err(InputError::MinDisplacement {
+                    distance,
+                    people: count,
+                    label,
+                    most: count.saturating_sub(2) / 2,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Warn if the minimum cycle length has short cycles to forbid among more
+    /// people than it's quick to forbid them one by one.
+    fn check_min_cycle_length(&self) {
+        let Some(min_length) = self.rules.min_cycle_length else {
+            return;
+        };
+        if min_length <= self.shortest_cycle() {
+            return;
+        }
+        for (label, draw) in self.draws() {
+            let count = draw.people.len();
+            if count > SHORT_CYCLE_PEOPLE {
+                warn!(
+                    "A min_cycle_length of {min_length} forbids each short cycle among {count} people{}, which may be slow; it's meant for {SHORT_CYCLE_PEOPLE} or fewer.",
+                    label.map(|l| format!(" in '{l}'")).unwrap_or_default()
+                );
+            }
+        }
+    }
+
+    /// Return the fewest people in a cycle: two if they may give to each
+    /// other, otherwise three.
+    fn shortest_cycle(&self) -> usize {
+        if self.allow_mutual {
+            2
+        } else {
+            3
+        }
+    }
+
+    /// Confirm the exact number of cycles is possible given the shortest
+    /// cycle.
+    fn check_exact_cycles(&self) -> Result<(), InputError> {
+        let Some(count) = self.rules.exact_cycles else {
+            return Ok(());
+        };
+        for (label, draw) in self.draws() {
+            let most = draw.people.len() / self.shortest_cycle();
+            if count == 0 || count > most {
+                return Err(InputError::ExactCycles {
+                    count,
+                    people: draw.people.len(),
+                    label,
+                    most,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Confirm the people in each different cycle set exist and could be in
+    /// different cycles given the shortest cycle.
+    fn check_different_cycle_sets(&self) -> Result<(), InputError> {
+        for set in &self.different_cycle_sets {
+            for name in set {
+                if !self.people.iter().any(|p| &p.name == name) {
+                    return Err(InputError::UnknownCycleSetName(name.clone()));
+                }
+            }
+            let most = self.people.len() / self.shortest_cycle();
+            if set.len() > most {
+                return Err(InputError::CycleSetTooLarge {
+                    set: set.clone(),
+                    most,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Warn that the whitelisted pairs, forced by the input, are known to
+    /// anyone who can read it.
+    pub fn public_warning(&self) -> Option<String> {
+        if self.whitelist.is_empty() {
+            return None;
+        }
+        let mut warning =
+            String::from("Warning: anyone with the input file knows these assignments:");
+        for pair in &self.whitelist {
+            warning.push_str(&format!("\n  {} gives to {}", pair.giver, pair.receiver));
+        }
+        Some(warning)
+    }
+
+    /// Confirm the input makes sense before anything is encoded.
+    fn validate(&self) -> Result<(), InputError> {
+        let mut names = HashSet::new();
+        for person in &self.people {
+            if !names.insert(&person.name) {
+                return Err(InputError::DuplicateName(person.name.clone()));
+            }
+        }
+        self.check_emails()?;
+        self.check_history()?;
+        self.check_exclude_givers()?;
+        self.check_couples()?;
+        self.check_groups()?;
+        self.check_whitelist()?;
+        self.check_gifts_per_person()?;
+        self.check_exact_cycles()?;
+        self.check_different_cycle_sets()?;
+        self.check_min_displacement()?;
+        self.check_min_cycle_length();
+        self.check_template()
+    }
+
+    /// Confirm the partners of each couple are in the people list.
+    fn check_couples(&self) -> Result<(), InputError> {
+        for (a, b) in &self.couples {
+            for name in [a, b] {
+                if !self.people.iter().any(|p| &p.name == name) {
+                    return Err(InputError::UnknownPartner(name.clone()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Confirm no whitelisted pair is also forbidden by the blacklist, a
+    /// blacklist set, or a household. Charities are exempt from those.
+    fn check_whitelist(&self) -> Result<(), InputError> {
+        let households = self.households();
+        for pair in &self.whitelist {
+            if self.is_charity(&pair.giver) || self.is_charity(&pair.receiver) {
+                continue;
+            }
+            let giver = pair.giver.clone();
+            let receiver = pair.receiver.clone();
+            if self.blacklist.contains(pair) {
+                return Err(InputError::WhitelistBlacklisted { giver, receiver });
+            }
+            if households
+                .iter()
+                .any(|h| h.contains(&pair.giver) && h.contains(&pair.receiver))
+            {
+                return Err(InputError::WhitelistHousehold { giver, receiver });
+            }
+        }
+        Ok(())
+    }
+
+    /// Use this template for the body of the messages if its placeholders
+    /// are all known.
+    pub fn set_template(&mut self, template: String) -> Result<(), String> {
+        self.template = Some(template);
+        self.check_template().map_err(|e| e.to_string())
+    }
+
+    /// Confirm every placeholder in the template is one of `PLACEHOLDERS`.
+    fn check_template(&self) -> Result<(), InputError> {
+        let Some(ref template) = self.template else {
+            return Ok(());
+        };
+        match placeholders(template).find(|name| !PLACEHOLDERS.contains(name)) {
+            Some(name) => Err(InputError::UnknownPlaceholder(name.to_string())),
+            None => Ok(()),
+        }
+    }
+
+    /// Confirm the email of everyone who's emailed looks like an address.
+    fn check_emails(&self) -> Result<(), InputError> {
+        // Charities and people contacted another way aren't emailed.
+        for person in self
+            .people
+            .iter()
+            .filter(|p| !p.charity && p.contact.is_none())
+        {
+            if !is_email(&person.email) {
+                return Err(InputError::InvalidEmail {
+                    person: person.name.clone(),
+                    email: person.email.clone(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Confirm all names present are in the people list. Warn about years
+    /// that aren't valid assignments.
+    fn check_history(&self) -> Result<(), InputError> {
+        for solution in &self.history {
+            for problem in solution.permutation_problems(self.gifts_each()) {
+                warn!("History for {}: {}.", solution.year, problem);
+            }
+            for pair in &solution.pairs {
+                if !self.people.iter().any(|p| p.name == pair.giver) {
+                    return Err(InputError::UnknownGiver(pair.giver.clone()));
+                }
+
+                if !self.people.iter().any(|p| p.name == pair.receiver) {
+                    return Err(InputError::UnknownReceiver(pair.receiver.clone()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Confirm the givers people won't receive from are in the people list.
+    /// Warn about anyone left with fewer than two possible givers.
+    fn check_exclude_givers(&self) -> Result<(), InputError> {
+        let households = self.households();
+        for person in &self.people {
+            for giver in &person.exclude_givers {
+                if !self.people.iter().any(|p| &p.name == giver) {
+                    return Err(InputError::MissingExcludedGiver {
+                        person: person.name.clone(),
+                        giver: giver.clone(),
+                    });
+                }
+            }
+            if person.exclude_givers.is_empty() {
+                continue;
+            }
+            let same_household = |name: &String| {
+                households
+                    .iter()
+                    .any(|h| h.contains(name) && h.contains(&person.name))
+            };
+            let possible = self
+                .people
+                .iter()
+                .filter(|p| p.name != person.name)
+                .filter(|p| !person.exclude_givers.contains(&p.name))
+                .filter(|p| !same_household(&p.name))
+                .count();
+            if possible < 2 {
+                warn!(
+                    "'{}' excludes all but {} possible givers.",
+                    person.name, possible
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize, Default)]
+pub struct Person {
+    pub name: String,
+    pub email: String,
+    /// How this person prefers to be contacted instead of email.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub contact: Option<Contact>,
+    /// Free-form attributes like department, used by `balance_attribute`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub attributes: BTreeMap<String, String>,
+    /// The language of this person's messages, e.g., "de". English if none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lang: Option<String>,
+    /// A charity receives one gift and gives none, so someone else goes
+    /// without. It's never emailed and ignores the history and blacklists.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub charity: bool,
+    /// The groups this person is a member of besides those listing them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<String>,
+    /// The household this person shares with anyone else naming it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub household: Option<String>,
+    /// The people this person doesn't want to receive from.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude_givers: Vec<String>,
+    /// The country this person lives in, used by `same_country_only`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+    /// What this person is hoping for, shown to their Secret Santa.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub wishlist: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct Contact {
+    /// The name of the method, e.g., "sms", matched against `--contact-exec`.
+    pub method: String,
+    pub address: String,
+}
+
+/// A giver and receiver. Pairs are equal when their giver and receiver are;
+/// the other fields are notes about the pair.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Pair<T>
+where
+    T: Debug + Eq + Hash + PartialEq + Clone,
+{
+    pub giver: T,
+    pub receiver: T,
+    /// The last year a blacklisted pair applies.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub until_year: Option<u16>,
+    /// What the giver gave in a historical pair.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gift: Option<String>,
+    /// Why a blacklisted or whitelisted pair is there. It's only shown, never
+    /// solved with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+impl<T> PartialEq for Pair<T>
+where
+    T: Debug + Eq + Hash + PartialEq + Clone,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.giver == other.giver && self.receiver == other.receiver
+    }
+}
+
+impl<T> Eq for Pair<T> where T: Debug + Eq + Hash + PartialEq + Clone {}
+
+impl<T> Hash for Pair<T>
+where
+    T: Debug + Eq + Hash + PartialEq + Clone,
+{
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.giver.hash(state);
+        self.receiver.hash(state);
+    }
+}
+
+impl<T> Pair<T>
+where
+    T: Debug + Eq + Hash + PartialEq + Clone,
+{
+    pub fn new(x: T, y: T) -> Self {
+        Self {
+            giver: x,
+            receiver: y,
+            until_year: None,
+            gift: None,
+            reason: None,
+        }
+    }
+}
+
+/// Encode the core rules. Each pair's variable is added once and the rules
+/// refer to it by number, so names aren't cloned for every literal; with
+/// hundreds of people there are O(n²) of them.
+fn encode_secret_santa_rules<T: Debug + Eq + Hash + PartialEq + Clone>(
+    universe: &[T],
+    allow_mutual: bool,
+    gifts: usize,
+    encoder: &mut Encoder<Pair<T>, impl Backend>,
+) {
+    let len = universe.len();
+    let mut vars = Vec::with_capacity(len * len);
+    for giver in universe {
+        for receiver in universe {
+            vars.push(
+                encoder
+                    .varmap
+                    .add_var(Pair::new(giver.clone(), receiver.clone())),
+            );
+        }
+    }
+    let var = |giver: usize, receiver: usize| vars[giver * len + receiver];
+    let lit = |giver: usize, receiver: usize| VarType::<Pair<T>>::Unnamed(var(giver, receiver));
+    let k = gifts as u32;
+    // Each person gives to `gifts` people.
+    for p in 0..len {
+        let lits = (0..len).map(|x| lit(p, x));
+        encoder.add_constraint(ExactlyK { k, lits });
+    }
+    // Each person receives from `gifts` people.
+    for p in 0..len {
+        let lits = (0..len).map(|x| lit(x, p));
+        encoder.add_constraint(ExactlyK { k, lits });
+    }
+
+    // No one can give to themselves.
+    for p in 0..len {
+        encoder.backend.add_clause(std::iter::once(-var(p, p)));
+    }
+
+    // Don't have small cycles, unless giving to each other is allowed.
+    if allow_mutual {
+        return;
+    }
+    for p in 0..len {
+        for j in p + 1..len {
+            encoder
+                .backend
+                .add_clause([-var(p, j), -var(j, p)].into_iter());
+        }
+    }
+}
+
+fn include_pairs<T: Debug + Eq + Hash + PartialEq + Clone>(
+    lits: impl Iterator<Item = Pair<T>> + Clone,
+    encoder: &mut Encoder<Pair<T>, impl Backend>,
+) {
+    // And of nothing is true anyway.
+    if lits.clone().next().is_none() {
+        return;
+    }
+    encoder.add_constraint(And(lits));
+}
+
+fn exclude_pairs<T: Debug + Eq + Hash + PartialEq + Clone>(
+    lits: impl Iterator<Item = Pair<T>> + Clone,
+    encoder: &mut Encoder<Pair<T>, impl Backend>,
+) {
+    // Or of nothing is false, so its Not is true anyway.
+    if lits.clone().next().is_none() {
+        return;
+    }
+    encoder.add_constraint(Not(Or(lits)));
+}
+
+fn exclude_some_pairs<T: Debug + Eq + Hash + PartialEq + Clone>(
+    lits: impl Iterator<Item = Pair<T>> + Clone,
+    encoder: &mut Encoder<Pair<T>, impl Backend>,
+) {
+    encoder.add_constraint(Not(And(lits)));
+}
+
+fn exclude_pairs_symmetric<T: Debug + Eq + Hash + PartialEq + Clone>(
+    lits: impl Iterator<Item = Pair<T>> + Clone,
+    encoder: &mut Encoder<Pair<T>, impl Backend>,
+) {
+    exclude_pairs(lits.clone(), encoder);
+    exclude_pairs(
+        lits.map(
+            |Pair {
+                 giver: a,
+                 receiver: b,
+                 ..
+             }| Pair::new(b, a),
+        ),
+        encoder,
+    );
+}
+
+fn exclude_sets<T: Debug + Eq + Hash + PartialEq + Clone>(
+    people: &[T],
+    encoder: &mut Encoder<Pair<T>, impl Backend>,
+) {
+    let len = people.len();
+    let mut accum = vec![];
+    for x in 0..len {
+        for y in x..len {
+            accum.push(Pair::new(people[x].clone(), people[y].clone()));
+        }
+    }
+    exclude_pairs_symmetric(accum.into_iter(), encoder);
+}
+
+/// Return true if the address has something before an `@` and a domain with
+/// a dot inside it. It's no more than a check for typos.
+fn is_email(address: &str) -> bool {
+    if address.chars().any(char::is_whitespace) {
+        return false;
+    }
+    let Some((local, domain)) = address.rsplit_once('@') else {
+        return false;
+    };
+    !local.is_empty()
+        && domain.split_once('.').is_some_and(|(name, rest)| {
+            !name.is_empty() && !rest.is_empty() && !rest.ends_with('.')
+        })
+}
+
+/// Return the last word of a name, or none if it has only one.
+fn surname(name: &str) -> Option<&str> {
+    let mut words = name.split_whitespace();
+    let last = words.next_back()?;
+    words.next().map(|_| last)
+}
+
+/// Return the size of a person's household. People who are not in any
+/// household are their own household of one.
+fn household_size(name: &str, households: &[Vec<String>]) -> usize {
+    households
+        .iter()
+        .filter(|h| h.iter().any(|n| n == name))
+        .map(|h| h.len())
+        .max()
+        .unwrap_or(1)
+}
+
+/// Penalize giving between households of different sizes.
+///
+/// Each pair costs the ratio of the larger household's size to the smaller
+/// one's minus one, so giving between households of equal size is free, and a
+/// single person giving to a household of four costs three.
+fn household_balance_penalty(pairs: &[Pair<String>], households: &[Vec<String>]) -> f64 {
+    pairs
+        .iter()
+        .map(|pair| {
+            let a = household_size(&pair.giver, households) as f64;
+            let b = household_size(&pair.receiver, households) as f64;
+            a.max(b) / a.min(b) - 1.0
+        })
+        .sum()
+}
+
+/// Return the pairs that would close a loop of giving of at most `max_length`
+/// with the most recent `lookback` years of history.
+fn gift_back_pairs(history: &[Solution], rule: GiftBack) -> Vec<Pair<String>> {
+    let mut recent: Vec<&Solution> = history.iter().collect();
+    recent.sort_by_key(|sol| Reverse(sol.year));
+    let mut next: HashMap<&str, BTreeSet<&str>> = HashMap::new();
+    for solution in recent.into_iter().take(rule.lookback) {
+        for pair in &solution.pairs {
+            next.entry(&pair.giver).or_default().insert(&pair.receiver);
+        }
+    }
+    let mut starts: Vec<&str> = next.keys().copied().collect();
+    starts.sort();
+    let mut pairs = vec![];
+    for y in starts {
+        // Everyone Y reaches within `max_length - 1` gifts closes a loop by
+        // giving to Y.
+        let mut reached = BTreeSet::new();
+        let mut frontier = vec![y];
+        for _ in 1..rule.max_length {
+            frontier = frontier
+                .iter()
+                .flat_map(|n| next.get(n).into_iter().flatten().copied())
+                .filter(|n| reached.insert(*n))
+                .collect();
+        }
+        for x in reached {
+            if x != y {
+                pairs.push(Pair::new(x.to_string(), y.to_string()));
+            }
+        }
+    }
+    pairs
+}
+
+/// Return the cycles of a solution. Each cycle starts with its alphabetically
+/// first giver and the cycles are sorted.
+fn cycles(pairs: &[Pair<String>]) -> Vec<Vec<String>> {
+    let next: HashMap<&str, &str> = pairs
+        .iter()
+        .map(|p| (p.giver.as_str(), p.receiver.as_str()))
+        .collect();
+    let mut givers: Vec<&str> = next.keys().copied().collect();
+    givers.sort();
+    let mut seen = HashSet::new();
+    let mut cycles = vec![];
+    for start in givers {
+        let mut cycle = vec![];
+        let mut current = start;
+        while seen.insert(current) {
+            cycle.push(current.to_string());
+            match next.get(current) {
+                Some(receiver) => current = receiver,
+                None => break,
+            }
+        }
+        if !cycle.is_empty() {
+            cycles.push(cycle);
+        }
+    }
+    cycles
+}
+
+/// Return true if every cycle includes someone with each value of the
+/// attribute. People without the attribute don't count toward any value.
+fn cycles_balanced(pairs: &[Pair<String>], people: &[Person], attribute: &str) -> bool {
+    let value = |name: &str| {
+        people
+            .iter()
+            .find(|p| p.name == name)
+            .and_then(|p| p.attributes.get(attribute))
+    };
+    let values: BTreeSet<&String> = people
+        .iter()
+        .filter_map(|p| p.attributes.get(attribute))
+        .collect();
+    cycles(pairs).iter().all(|cycle| {
+        let present: BTreeSet<&String> = cycle.iter().filter_map(|n| value(n)).collect();
+        present == values
+    })
+}
+
+/// Return true if the pairs repeat a whole cycle from the last `lookback`
+/// years of history. History is expected newest first.
+fn repeats_cycle(pairs: &[Pair<String>], history: &[Solution], lookback: usize) -> bool {
+    let past: HashSet<Vec<String>> = history
+        .iter()
+        .take(lookback)
+        .flat_map(|solution| cycles(&solution.pairs))
+        .collect();
+    cycles(pairs).iter().any(|cycle| past.contains(cycle))
+}
+
+/// Return the inverse of a solution: each receiver gives to their giver.
+fn inverse(pairs: &[Pair<String>]) -> Vec<Pair<String>> {
+    pairs
+        .iter()
+        .map(|p| Pair::new(p.receiver.clone(), p.giver.clone()))
+        .collect()
+}
+
+/// Return true if a solution reverses the latest solution in history, or
+/// more than `max` of its pairs if given.
+fn reverses_latest(pairs: &[Pair<String>], history: &[Solution], max: Option<usize>) -> bool {
+    let Some(latest) = history.iter().max_by_key(|solution| solution.year) else {
+        return false;
+    };
+    let reversed = overlap(pairs, &inverse(&latest.pairs));
+    reversed == pairs.len() || max.is_some_and(|max| reversed > max)
+}
+
+/// Return true if a cycle's members are all in one household or all share an
+/// attribute's value.
+fn has_island(pairs: &[Pair<String>], people: &[Person], households: &[Vec<String>]) -> bool {
+    let person = |name: &str| people.iter().find(|p| p.name == name);
+    cycles(pairs).iter().any(|cycle| {
+        let in_household = households
+            .iter()
+            .any(|household| cycle.iter().all(|name| household.contains(name)));
+        let members: Vec<&Person> = cycle.iter().filter_map(|name| person(name)).collect();
+        let share_value = members.first().is_some_and(|first| {
+            first.attributes.iter().any(|(key, value)| {
+                members
+                    .iter()
+                    .all(|member| member.attributes.get(key) == Some(value))
+            })
+        });
+        in_household || share_value
+    })
+}
+
+/// Return the decayed count of historical pairs these pairs repeat. History
+/// is expected newest first.
+fn history_penalty(pairs: &[Pair<String>], history: &[Solution], decay: f32) -> f64 {
+    history
+        .iter()
+        .enumerate()
+        .map(|(age, solution)| {
+            let repeats = pairs.iter().filter(|p| solution.pairs.contains(p)).count();
+            repeats as f64 * (decay as f64).powi(age as i32)
+        })
+        .sum()
+}
+
+/// Keep only the solutions with the lowest cost.
+fn retain_best(solutions: &mut Vec<Vec<Pair<String>>>, cost: impl Fn(&[Pair<String>]) -> f64) {
+    let costs: Vec<f64> = solutions.iter().map(|pairs| cost(pairs)).collect();
+    let best = costs.iter().cloned().fold(f64::INFINITY, f64::min);
+    let mut costs = costs.into_iter();
+    solutions.retain(|_| costs.next().unwrap() - best < 1e-9);
+}
+
+/// Who the footer of every message credits, e.g., a fork or a company's own
+/// deployment.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Branding {
+    pub name: String,
+    pub url: String,
+}
+
+impl Default for Branding {
+    fn default() -> Self {
+        Branding {
+            name: String::from("secret-santa"),
+            url: String::from("https://github.com/shanecelis/secret-santa"),
+        }
+    }
+}
+
+impl Branding {
+    /// Return the footer that ends every message.
+    pub fn footer(&self) -> String {
+        format!(
+            "\n* * *\nBrought to you by {}[1].\n\n[1]: {}\n",
+            self.name, self.url
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub subject: String,
+    pub body: String,
+    pub email: String,
+    pub contact: Option<Contact>,
+}
+
+/// Return the givers for this person.
+fn givers_for<'a>(receiver: &'a str, input: &'a Input) -> impl Iterator<Item = String> + use<'a> {
+    input.history.iter().flat_map(move |x| {
+        x.pairs
+            .iter()
+            .filter(move |p| p.receiver == receiver)
+            .map(|p| format!("{} ({})", p.giver, x.year))
+    })
+}
+
+/// Return the receivers for this person.
+fn receivers_for<'a>(giver: &'a str, input: &'a Input) -> impl Iterator<Item = String> + use<'a> {
+    input.history.iter().flat_map(move |x| {
+        x.pairs
+            .iter()
+            .filter(move |p| p.giver == giver)
+            .map(|p| format!("{} ({})", p.receiver, x.year))
+    })
+}
+
+/// Return what this person gave in the past.
+fn gifts_for<'a>(giver: &'a str, input: &'a Input) -> impl Iterator<Item = String> + use<'a> {
+    input.history.iter().flat_map(move |x| {
+        x.pairs
+            .iter()
+            .filter(move |p| p.giver == giver)
+            .filter_map(|p| p.gift.as_ref())
+            .map(|gift| format!("{} ({})", gift, x.year))
+    })
+}
+
+/// How a language joins a list of names.
+struct ListWords {
+    separator: &'static str,
+    /// Goes before the last name instead of the separator.
+    last_separator: &'static str,
+}
+
+impl ListWords {
+    /// Return the list words for a language, falling back to English.
+    fn for_lang(lang: Option<&str>) -> ListWords {
+        let last_separator = match lang.map(|l| l.split(['-', '_']).next().unwrap_or(l)) {
+            Some("de") => " und ",
+            Some("es") => " y ",
+            Some("fr") => " et ",
+            Some("it") => " e ",
+            Some("nl") => " en ",
+            _ => ", and ",
+        };
+        ListWords {
+            separator: ", ",
+            last_separator,
+        }
+    }
+
+    /// Join at most `limit` items, e.g., "A, B, …and 2 earlier".
+    fn join_limited(&self, items: &[impl fmt::Display], limit: Option<usize>) -> String {
+        let limit = limit.unwrap_or(items.len());
+        if items.len() <= limit {
+            return self.join(items);
+        }
+        let earlier = format!("…and {} earlier", items.len() - limit);
+        if limit == 0 {
+            return earlier;
+        }
+        let shown: Vec<String> = items[..limit].iter().map(|i| i.to_string()).collect();
+        format!(
+            "{}{}{}",
+            shown.join(self.separator),
+            self.separator,
+            earlier
+        )
+    }
+
+    /// Join the items, e.g., "A, B, and C".
+    fn join(&self, items: &[impl fmt::Display]) -> String {
+        let mut list = String::new();
+        for (i, item) in items.iter().enumerate() {
+            if i > 0 {
+                list.push_str(if i + 1 == items.len() {
+                    self.last_separator
+                } else {
+                    self.separator
+                });
+            }
+            list.push_str(&item.to_string());
+        }
+        list
+    }
+}
+
+/// Noted in a message whose optional sections were left out to fit
+/// `max_body_length`.
+const TRUNCATED: &str = "\n(Some details were left out.)\n";
+
+/// The placeholders a template may use. Those without a value, e.g.,
+/// `{past_gifts}` for someone who's given none, are left empty.
+pub const PLACEHOLDERS: [&str; 12] = [
+    "giver",
+    "receiver",
+    "group",
+    "fallback",
+    "past_receivers",
+    "past_givers",
+    "receiver_past_givers",
+    "past_gifts",
+    "wishlist",
+    "budget",
+    "event_date",
+    "event_location",
+];
+
+/// Return the placeholders of a template in order: words of lowercase
+/// letters and underscores in braces. Other braces are left as they are.
+fn placeholders(template: &str) -> impl Iterator<Item = &str> {
+    template
+        .match_indices('{')
+        .filter_map(|(i, _)| placeholder(&template[i..]))
+}
+
+/// Return the placeholder at the start of the text, if there is one.
+fn placeholder(text: &str) -> Option<&str> {
+    let end = text.find('}')?;
+    let name = &text[1..end];
+    (!name.is_empty() && name.chars().all(|c| c.is_ascii_lowercase() || c == '_')).then_some(name)
+}
+
+/// Return the template with its placeholders replaced by their values.
+fn render(template: &str, values: &HashMap<&str, String>) -> String {
+    let mut out = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        match placeholder(rest) {
+            Some(name) => {
+                out.push_str(values.get(name).map_or("", String::as_str));
+                rest = &rest[name.len() + 2..];
+            }
+            None => {
+                out.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+fn compose_message(pair: &Pair<String>, input: &Input, draw: &Draw) -> Result<Message, fmt::Error> {
+    let giver = &pair.giver;
+    let receiver = &pair.receiver;
+    let subject = format!("Secret Santa {giver}: Keep it secret! Keep it safe!");
+    let person = input
+        .people
+        .iter()
+        .find(|p| p.name == pair.giver)
+        .expect("Failed to find email address");
+    let name_and_email = format!("{} <{}>", pair.giver, person.email);
+    if let Some(ref template) = input.template {
+        let mut body = render(template, &template_values(pair, input, draw));
+        if !body.ends_with('\n') {
+            body.push('\n');
+        }
+        writeln!(body, "{}", input.footer())?;
+        return Ok(Message {
+            subject,
+            body,
+            email: name_and_email,
+            contact: person.contact.clone(),
+        });
+    }
+    let mut body = String::new();
+    writeln!(body, "{giver}, you are the Secret Santa for {receiver}.")?;
+    if let Some(ref group) = draw.label {
+        writeln!(body, "You're in the '{group}' exchange.")?;
+    }
+    if let Some(fallback) = draw.fallback_for(giver) {
+        writeln!(
+            body,
+            "If {receiver} can't take part, be the Secret Santa for {fallback} instead."
+        )?;
+    }
+    // When, where, and how much, as one paragraph.
+    let mut event = vec![];
+    match (&input.event_date, &input.event_location) {
+        (Some(date), Some(location)) => {
+            event.push(format!("The gift exchange is {date} at {location}."))
+        }
+        (Some(date), None) => event.push(format!("The gift exchange is {date}.")),
+        (None, Some(location)) => event.push(format!("The gift exchange is at {location}.")),
+        (None, None) => {}
+    }
+    if let Some(ref budget) = input.budget {
+        event.push(format!("Suggested spend: {budget}."));
+    }
+    if !event.is_empty() {
+        writeln!(body, "\n{}", event.join("\n"))?;
+    }
+
+    let words = ListWords::for_lang(person.lang.as_deref());
+
+    // The optional sections in the order they're left out to fit
+    // `max_body_length`.
+    let mut sections = vec![];
+    let receivers: Vec<String> = receivers_for(giver, input).collect();
+    if !receivers.is_empty() {
+        sections.push(format!(
+            "\nYou were Secret Santa for {}.\n",
+            words.join_limited(&receivers, input.history_message_limit)
+        ));
+    }
+
+    let givers: Vec<String> = givers_for(giver, input).collect();
+    if !givers.is_empty() {
+        sections.push(format!(
+            "\nYou had these Secret Santas in Christmases past: {}.\n",
+            words.join_limited(&givers, input.history_message_limit)
+        ));
+    }
+
+    // The receiver's past santas, so the giver can avoid their gifts.
+    let receiver_givers: Vec<String> = givers_for(receiver, input).collect();
+    if !receiver_givers.is_empty() {
+        sections.push(format!(
+            "\n{receiver} had these Secret Santas in Christmases past: {}.\n",
+            words.join_limited(&receiver_givers, input.history_message_limit)
+        ));
+    }
+
+    let gifts: Vec<String> = gifts_for(giver, input).collect();
+    if !gifts.is_empty() {
+        sections.push(format!("\nYou previously gave: {}.\n", words.join(&gifts)));
+    }
+
+    let wishlist = wishlist_of(receiver, input);
+    if !wishlist.is_empty() {
+        sections.push(format!(
+            "\nHere's what {receiver} is hoping for: {}.\n",
+            words.join(wishlist)
+        ));
+    }
+
+    let footer = format!("{}\n", input.footer());
+    let len = |sections: &[String]| {
+        body.chars().count()
+            + sections.iter().map(|s| s.chars().count()).sum::<usize>()
+            + footer.chars().count()
+    };
+    let mut omitted = 0;
+    if let Some(max) = input.max_body_length {
+        let note = TRUNCATED.chars().count();
+        if !sections.is_empty() && len(&sections) > max {
+            omitted = 1;
+            while omitted < sections.len() && len(&sections[omitted..]) + note > max {
+                omitted += 1;
+            }
+        }
+    }
+    // Sections are dropped from the front, but what's left keeps its order.
+    for section in &sections[omitted..] {
+        body.push_str(section);
+    }
+    if omitted > 0 {
+        body.push_str(TRUNCATED);
+    }
+    body.push_str(&footer);
+
+    Ok(Message {
+        subject,
+        body,
+        email: name_and_email,
+        contact: person.contact.clone(),
+    })
+}
+
+/// Return the receiver's wishlist, empty if they have none.
+fn wishlist_of<'a>(receiver: &str, input: &'a Input) -> &'a [String] {
+    input
+        .people
+        .iter()
+        .find(|p| p.name == receiver)
+        .map_or(&[], |p| &p.wishlist)
+}
+
+/// Return the value of each placeholder for the pair's message.
+fn template_values<'a>(
+    pair: &Pair<String>,
+    input: &Input,
+    draw: &Draw,
+) -> HashMap<&'a str, String> {
+    let giver = &pair.giver;
+    let lang = input
+        .people
+        .iter()
+        .find(|p| &p.name == giver)
+        .and_then(|p| p.lang.as_deref());
+    let words = ListWords::for_lang(lang);
+    let receivers: Vec<String> = receivers_for(giver, input).collect();
+    let givers: Vec<String> = givers_for(giver, input).collect();
+    let receiver_givers: Vec<String> = givers_for(&pair.receiver, input).collect();
+    let gifts: Vec<String> = gifts_for(giver, input).collect();
+    let limit = input.history_message_limit;
+    let optional = |value: &Option<String>| value.clone().unwrap_or_default();
+    HashMap::from([
+        ("giver", giver.clone()),
+        ("receiver", pair.receiver.clone()),
+        ("group", optional(&draw.label)),
+        (
+            "fallback",
+            draw.fallback_for(giver).unwrap_or_default().to_string(),
+        ),
+        ("past_receivers", words.join_limited(&receivers, limit)),
+        ("past_givers", words.join_limited(&givers, limit)),
+        (
+            "receiver_past_givers",
+            words.join_limited(&receiver_givers, limit),
+        ),
+        ("past_gifts", words.join(&gifts)),
+        ("wishlist", words.join(wishlist_of(&pair.receiver, input))),
+        ("budget", optional(&input.budget)),
+        ("event_date", optional(&input.event_date)),
+        ("event_location", optional(&input.event_location)),
+    ])
+}
+
+/// Compose a reminder for someone who hasn't confirmed their assignment. It
+/// doesn't repeat the assignment.
+fn compose_reminder(person: &Person, footer: &str) -> Message {
+    let name = &person.name;
+    let subject = format!("Secret Santa {name}: A gentle reminder");
+    let body = format!(
+        "{name}, we haven't heard back from you yet. Please confirm that you \
+         received your Secret Santa assignment.\n{footer}\n"
+    );
+    Message {
+        subject,
+        body,
+        email: format!("{} <{}>", person.name, person.email),
+        contact: person.contact.clone(),
+    }
+}
+
+/// Compose reminders for the named people only.
+pub fn compose_reminders(names: &[String], input: &Input) -> Vec<Message> {
+    for name in names {
+        if !input.people.iter().any(|p| &p.name == name) {
+            warn!(
+                "Named '{}' to remind but not found in people set; skipping.",
+                name
+            );
+        }
+    }
+    input
+        .people
+        .iter()
+        .filter(|p| names.contains(&p.name))
+        .map(|person| compose_reminder(person, &input.footer()))
+        .collect()
+}
+
+/// Return the current year.
+pub fn this_year() -> u16 {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Clock is before 1970")
+        .as_secs();
+    // Convert days since 1970-01-01 to a year using Howard Hinnant's
+    // civil_from_days algorithm.
+    let z = (secs / 86_400) as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let year = yoe + era * 400 + if mp >= 10 { 1 } else { 0 };
+    year as u16
+}
+
+/// Return the delivery commands: the `--exec` flags if given, otherwise the
+/// input file's `exec` field.
+pub fn delivery_commands<'a>(flags: &'a [String], input: &'a Input) -> Vec<&'a str> {
+    if flags.is_empty() {
+        input.exec.iter().map(String::as_str).collect()
+    } else {
+        flags.iter().map(String::as_str).collect()
+    }
+}
+
+/// An SMTP server to send email through instead of running a command.
+#[derive(Debug, Clone)]
+pub struct Smtp {
+    pub server: String,
+    /// 465 connects with TLS; any other port upgrades to it with STARTTLS.
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Who the messages are from, e.g., "Santa <santa@email.com>".
+    pub from: String,
+}
+
+impl Smtp {
+    /// Send the message.
+    #[cfg_attr(not(feature = "smtp"), allow(unused_variables))]
+    pub fn send(&self, msg: &Message) -> std::io::Result<()> {
+        #[cfg(feature = "smtp")]
+        {
+            use lettre::{transport::smtp::authentication::Credentials, SmtpTransport, Transport};
+            let mailbox = |address: &str| {
+                address
+                    .parse::<lettre::message::Mailbox>()
+                    .map_err(std::io::Error::other)
+            };
+            let email = lettre::Message::builder()
+                .from(mailbox(&self.from)?)
+                .to(mailbox(&msg.email)?)
+                .subject(&msg.subject)
+                .body(msg.body.clone())
+                .map_err(std::io::Error::other)?;
+            let relay = if self.port == 465 {
+                SmtpTransport::relay(&self.server)
+            } else {
+                SmtpTransport::starttls_relay(&self.server)
+            };
+            let mut transport = relay.map_err(std::io::Error::other)?.port(self.port);
+            if let (Some(username), Some(password)) = (&self.username, &self.password) {
+                transport =
+                    transport.credentials(Credentials::new(username.clone(), password.clone()));
+            }
+            transport
+                .build()
+                .send(&email)
+                .map(|_| ())
+                .map_err(std::io::Error::other)
+        }
+        #[cfg(not(feature = "smtp"))]
+        Err(std::io::Error::other(
+            "Built without the smtp feature; messages can't be sent over SMTP.",
+        ))
+    }
+}
+
+/// How to deliver messages.
+pub struct Delivery<'a> {
+    /// The commands for email in the order to try them.
+    pub exec: Vec<&'a str>,
+    /// The commands for other contact methods.
+    pub contact_exec: &'a [(String, String)],
+    /// The server to send email through instead of the commands.
+    pub smtp: Option<Smtp>,
+    pub dry_run: bool,
+    /// Show each message on a dry run. They name the receivers, so they're
+    /// hidden otherwise.
+    pub verbose: bool,
+}
+
+impl Delivery<'_> {
+    /// Return the command and address to deliver a message with. People
+    /// whose contact method has no command are sent email.
+    fn route<'m>(&'m self, msg: &'m Message) -> Option<(&'m str, &'m str)> {
+        self.routes(msg).into_iter().next()
+    }
+
+    /// Return the command and address for the message's contact method.
+    fn contact_route<'m>(&'m self, msg: &'m Message) -> Option<(&'m str, &'m str)> {
+        let contact = msg.contact.as_ref()?;
+        self.contact_exec
+            .iter()
+            .find(|(method, _)| *method == contact.method)
+            .map(|(_, command)| (command.as_str(), contact.address.as_str()))
+    }
+
+    /// Return the commands and addresses to try in order.
+    fn routes<'m>(&'m self, msg: &'m Message) -> Vec<(&'m str, &'m str)> {
+        if let Some(route) = self.contact_route(msg) {
+            return vec![route];
+        }
+        self.exec
+            .iter()
+            .map(|exec| (*exec, msg.email.as_str()))
+            .collect()
+    }
+
+    /// Combine the messages that go to the same address into one message.
+    pub fn combine(&self, msgs: Vec<Message>) -> Vec<Message> {
+        let mut combined: Vec<(String, Message)> = vec![];
+        for msg in msgs {
+            // Compare only the address part of "Name <address>".
+            let address = self
+                .route(&msg)
+                .map(|(_, address)| address)
+                .unwrap_or(&msg.email);
+            let address = address
+                .rsplit_once('<')
+                .map(|(_, rest)| rest.trim_end_matches('>'))
+                .unwrap_or(address)
+                .to_lowercase();
+            if let Some((_, first)) = combined.iter_mut().find(|(a, _)| *a == address) {
+                first.subject = String::from("Secret Santa: Keep it secret! Keep it safe!");
+                first.body.push_str("\n---\n\n");
+                first.body.push_str(&msg.body);
+            } else {
+                combined.push((address, msg));
+            }
+        }
+        combined.into_iter().map(|(_, msg)| msg).collect()
+    }
+
+    /// Send each message with its delivery command.
+    pub fn deliver(&self, msgs: Vec<Message>) -> std::io::Result<()> {
+        for msg in &msgs {
+            self.deliver_one(msg)?;
+        }
+        Ok(())
+    }
+
+    /// Return what a dry run shows for the message: how it would be sent
+    /// and, if `verbose`, the message itself.
+    fn dry_run_output(&self, msg: &Message) -> String {
+        let route = match (&self.smtp, self.route(msg)) {
+            (Some(smtp), _) if self.contact_route(msg).is_none() => format!(
+                "smtp://{}:{} from {} to {}\n",
+                smtp.server, smtp.port, smtp.from, msg.email
+            ),
+            (_, Some((exec, address))) => {
+                let exec_args: Vec<&str> = exec.split_whitespace().collect();
+                format!("{} -s {} {address}\n", exec_args.join(" "), msg.subject)
+            }
+            (_, None) => return String::new(),
+        };
+        if self.verbose {
+            dry_run_message(msg) + &route
+        } else {
+            route
+        }
+    }
+
+    /// Send a message, trying each of its delivery commands in turn.
+    pub fn deliver_one(&self, msg: &Message) -> std::io::Result<()> {
+        if self.dry_run {
+            print!("{}", self.dry_run_output(msg));
+            return Ok(());
+        }
+        if let (Some(smtp), None) = (&self.smtp, self.contact_route(msg)) {
+            return smtp.send(msg);
+        }
+        let mut result = Ok(());
+        for (exec, address) in self.routes(msg) {
+            #[cfg(feature = "exec")]
+            {
+                let exec_args: Vec<&str> = exec.split_whitespace().collect();
+                let subject = &msg.subject;
+                let body = &msg.body;
+                result = run_cmd!(echo $body | $[exec_args] -s $subject $address);
+            }
+            #[cfg(not(feature = "exec"))]
+            {
+                result = Err(std::io::Error::other(
+                    "Built without the exec feature; messages can't be sent.",
+                ));
+            }
+            match result {
+                Ok(()) => break,
+                Err(ref e) => warn!("'{exec}' failed for {address}: {e}"),
+            }
+        }
+        result
+    }
+}
+
+/// Compose the organizer's digest of who was sent their message. It only
+/// lists the assignments if given them.
+pub fn compose_digest(
+    organizer: &str,
+    statuses: &[(String, Result<(), String>)],
+    assignments: Option<&[Draw]>,
+    footer: &str,
+) -> Message {
+    let mut body = String::new();
+    let sent = statuses.iter().filter(|(_, status)| status.is_ok()).count();
+    body.push_str(&format!("Sent {} of {} messages.\n", sent, statuses.len()));
+    for (email, status) in statuses {
+        match status {
+            Ok(()) => body.push_str(&format!("\nSent: {email}")),
+            Err(e) => body.push_str(&format!("\nFailed: {email} ({e})")),
+        }
+    }
+    body.push('\n');
+    if let Some(draws) = assignments {
+        body.push_str("\nAssignments:\n");
+        for draw in draws {
+            for pair in &draw.pairs {
+                body.push_str(&format!("{} gives to {}\n", pair.giver, pair.receiver));
+            }
+        }
+    }
+    body.push_str(footer);
+    Message {
+        subject: String::from("Secret Santa: Delivery digest"),
+        body,
+        email: organizer.to_string(),
+        contact: None,
+    }
+}
+
+/// What a mail server said about an address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProbeResult {
+    Accepted,
+    Rejected(String),
+    Unknown(String),
+}
+
+/// Something that can ask whether an address accepts mail.
+pub trait Probe {
+    fn probe(&mut self, address: &str) -> ProbeResult;
+}
+
+/// Probes an address by asking its mail server `RCPT TO` without sending a
+/// message.
+pub struct SmtpProbe {
+    pub timeout: Duration,
+}
+
+impl SmtpProbe {
+    /// Return the mail server for a domain, preferring its MX record.
+    fn mail_server(domain: &str) -> String {
+        #[cfg(feature = "exec")]
+        let mx = run_fun!(dig +short MX $domain 2>/dev/null).unwrap_or_default();
+        #[cfg(not(feature = "exec"))]
+        let mx = String::new();
+        mx.lines()
+            .filter_map(|line| {
+                let (preference, host) = line.split_once(' ')?;
+                Some((preference.parse::<u32>().ok()?, host.trim_end_matches('.')))
+            })
+            .min()
+            .map(|(_, host)| host.to_string())
+            .unwrap_or_else(|| domain.to_string())
+    }
+
+    /// Read a possibly multiline SMTP reply and return its last line.
+    fn reply(reader: &mut impl BufRead) -> std::io::Result<String> {
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line)? == 0 {
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
+            }
+            if line.as_bytes().get(3) != Some(&b'-') {
+                return Ok(line.trim_end().to_string());
+            }
+        }
+    }
+
+    /// Return the server's reply to `RCPT TO` for the address.
+    fn rcpt(&self, domain: &str, address: &str) -> std::io::Result<String> {
+        let server = Self::mail_server(domain);
+        let stream = TcpStream::connect((server.as_str(), 25))?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        let mut writer = stream.try_clone()?;
+        let mut reader = BufReader::new(stream);
+        Self::reply(&mut reader)?;
+        for command in ["EHLO localhost".to_string(), "MAIL FROM:<>".to_string()] {
+            write!(writer, "{command}\r\n")?;
+            Self::reply(&mut reader)?;
+        }
+        write!(writer, "RCPT TO:<{address}>\r\n")?;
+        let reply = Self::reply(&mut reader)?;
+        write!(writer, "QUIT\r\n")?;
+        Ok(reply)
+    }
+}
+
+impl Probe for SmtpProbe {
+    fn probe(&mut self, address: &str) -> ProbeResult {
+        let Some((_, domain)) = address.rsplit_once('@') else {
+            return ProbeResult::Rejected(String::from("no domain"));
+        };
+        match self.rcpt(domain, address) {
+            Ok(reply) if reply.starts_with('2') => ProbeResult::Accepted,
+            Ok(reply) if reply.starts_with('5') => ProbeResult::Rejected(reply),
+            Ok(reply) => ProbeResult::Unknown(reply),
+            Err(e) => ProbeResult::Unknown(e.to_string()),
+        }
+    }
+}
+
+/// Probe each person's email and return those whose address wasn't accepted.
+pub fn check_emails<'a>(
+    people: &'a [Person],
+    probe: &mut impl Probe,
+) -> Vec<(&'a Person, ProbeResult)> {
+    people
+        .iter()
+        .filter(|person| !person.charity)
+        .map(|person| (person, probe.probe(&person.email)))
+        .filter(|(_, result)| *result != ProbeResult::Accepted)
+        .collect()
+}
+
+/// The wall-clock time spent in each phase of a run.
+#[derive(Debug)]
+pub struct Timings {
+    start: Instant,
+    phases: Vec<(&'static str, Duration)>,
+    solves: u32,
+}
+
+impl Default for Timings {
+    fn default() -> Self {
+        Timings {
+            start: Instant::now(),
+            phases: vec![],
+            solves: 0,
+        }
+    }
+}
+
+impl Timings {
+    /// Add the time since `start` to the phase.
+    pub fn add(&mut self, phase: &'static str, start: Instant) {
+        let elapsed = start.elapsed();
+        match self.phases.iter_mut().find(|(p, _)| *p == phase) {
+            Some((_, total)) => *total += elapsed,
+            None => self.phases.push((phase, elapsed)),
+        }
+    }
+
+    /// Run `f` and add the time it took to the phase.
+    fn time<T>(&mut self, phase: &'static str, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        self.add(phase, start);
+        result
+    }
+}
+
+impl fmt::Display for Timings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (phase, elapsed) in &self.phases {
+            write!(f, "{phase}: {elapsed:.2?}")?;
+            if *phase == "solving" && self.solves > 0 {
+                write!(
+                    f,
+                    " ({:.2?} per solve over {} solves)",
+                    *elapsed / self.solves,
+                    self.solves
+                )?;
+            }
+            writeln!(f)?;
+        }
+        write!(f, "total: {:.2?}", self.start.elapsed())
+    }
+}
+
+/// A line of the audit log. It describes a run but never its assignments.
+#[derive(Debug, Default)]
+pub struct AuditEntry {
+    pub timestamp: u64,
+    pub input_hash: String,
+    pub people: usize,
+    pub solutions: usize,
+    pub chosen: Vec<usize>,
+    /// The seed the solutions were chosen with, if any, to reproduce the run.
+    pub seed: Option<u64>,
+    pub delivery: &'static str,
+    pub outcome: String,
+}
+
+impl fmt::Display for AuditEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "timestamp={} input={} people={} solutions={} chosen={} seed={} delivery={} outcome={}",
+            self.timestamp,
+            self.input_hash,
+            self.people,
+            self.solutions,
+            if self.chosen.is_empty() {
+                String::from("none")
+            } else {
+                self.chosen
+                    .iter()
+                    .map(|i| i.to_string())
+                    .collect::<Vec<_>>()
+                    .join(",")
+            },
+            self.seed
+                .map_or(String::from("none"), |seed| seed.to_string()),
+            self.delivery,
+            self.outcome
+        )
+    }
+}
+
+/// Append an entry to the audit log.
+pub fn append_audit(path: &Path, entry: &AuditEntry) -> std::io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", entry)
+}
+
+/// Return the SHA-256 of some bytes as hex.
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn extract_pos<V>(model: Model<V>) -> Vec<V>
+where
+    V: Clone,
+    V: Eq,
+    V: Hash,
+    V: Debug,
+{
+    model
+        .vars()
+        .filter_map(|v| v.is_pos().then(|| v.unwrap()))
+        .collect()
+}
+
+/// Encode the rules and all the constraints from the input.
+fn encode_input(input: &Input, encoder: &mut Encoder<Pair<String>, impl Backend>) {
+    encode_secret_santa_rules(
+        &input.names(),
+        input.allow_mutual,
+        input.gifts_each(),
+        encoder,
+    );
+    encode_constraints(input, encoder);
+}
+
+/// Require the people in the set to be in different cycles.
+///
+/// For each person A a new variable per person X says X is reachable from A:
+/// A is, and if X is and X gives to Y, so is Y. Then no one else in the set
+/// may be reachable from A. The variables may be true for people who aren't
+/// reachable, but that only makes the constraint harder to satisfy, and
+/// setting them to exactly the reachable people always works.
+fn encode_different_cycles(
+    set: &[String],
+    names: &[String],
+    encoder: &mut Encoder<Pair<String>, impl Backend>,
+) {
+    for (i, a) in set.iter().enumerate() {
+        let index = |name: &String| names.iter().position(|n| n == name);
+        let Some(a) = index(a) else {
+            continue;
+        };
+        let reachable: Vec<i32> = names.iter().map(|_| encoder.varmap.new_var()).collect();
+        encoder.backend.add_clause(std::iter::once(reachable[a]));
+        for (x, giver) in names.iter().enumerate() {
+            for (y, receiver) in names.iter().enumerate() {
+                let gives = encoder
+                    .varmap
+                    .add_var(Pair::new(giver.clone(), receiver.clone()));
+                encoder
+                    .backend
+                    .add_clause([-reachable[x], -gives, reachable[y]].into_iter());
+            }
+        }
+        for b in set[i + 1..].iter().filter_map(index) {
+            encoder.backend.add_clause(std::iter::once(-reachable[b]));
+        }
+    }
+}
+
+/// Call `f` with each cycle of `length` out of `n` people, once per cycle:
+/// the first in it is the least.
+fn visit_cycles(n: usize, length: usize, cycle: &mut Vec<usize>, f: &mut impl FnMut(&[usize])) {
+    if cycle.len() == length {
+        f(cycle);
+        return;
+    }
+    let start = cycle.first().map_or(0, |first| first + 1);
+    for next in start..n {
+        if cycle.contains(&next) || (cycle.is_empty() && next + length > n) {
+            continue;
+        }
+        cycle.push(next);
+        visit_cycles(n, length, cycle, f);
+        cycle.pop();
+    }
+}
+
+/// Forbid every cycle of givers with fewer than `min_length` people. Those of
+/// two are already forbidden by the core rules unless `allow_mutual`.
+fn exclude_short_cycles(
+    names: &[String],
+    min_length: usize,
+    allow_mutual: bool,
+    encoder: &mut Encoder<Pair<String>, impl Backend>,
+) {
+    let shortest = if allow_mutual { 2 } else { 3 };
+    for length in shortest..min_length {
+        visit_cycles(names.len(), length, &mut vec![], &mut |cycle| {
+            let lits = (0..length).map(|i| {
+                Pair::new(
+                    names[cycle[i]].clone(),
+                    names[cycle[(i + 1) % length]].clone(),
+                )
+            });
+            encoder.add_constraint(Not(And(lits)));
+        });
+    }
+}
+
+/// Encode the input's constraints that go on top of the core rules.
+fn encode_constraints(input: &Input, encoder: &mut Encoder<Pair<String>, impl Backend>) {
+    let names = input.names();
+    debug!(
+        "Encoding {} people with {} blacklisted pairs, {} blacklist sets, {} whitelisted pairs, and {} years of history.",
+        names.len(),
+        input.blacklist.len(),
+        input.blacklist_sets.len(),
+        input.whitelist.len(),
+        input.history.len()
+    );
+    for set in &input.different_cycle_sets {
+        encode_different_cycles(set, &names, encoder);
+    }
+    if let Some(min_length) = input.rules.min_cycle_length {
+        exclude_short_cycles(&names, min_length, input.allow_mutual, encoder);
+    }
+    if let Some(distance) = input.rules.min_displacement {
+        exclude_pairs(input.too_close_pairs(distance).into_iter(), encoder);
+    }
+    // Charities are exempt from the history and blacklists.
+    let no_charity =
+        |pair: &Pair<String>| !input.is_charity(&pair.giver) && !input.is_charity(&pair.receiver);
+    for household in &input.couple_households() {
+        let household: Vec<String> = household
+            .iter()
+            .filter(|name| !input.is_charity(name))
+            .cloned()
+            .collect();
+        exclude_sets(&household, encoder);
+    }
+    exclude_pairs(
+        input.blacklist.iter().filter(|p| no_charity(p)).cloned(),
+        encoder,
+    );
+    for person in &input.people {
+        let pairs = person
+            .exclude_givers
+            .iter()
+            .map(|giver| Pair::new(giver.clone(), person.name.clone()));
+        exclude_pairs(pairs.filter(no_charity), encoder);
+    }
+    if input.rules.forbid_same_surname {
+        for set in input.surname_sets() {
+            exclude_sets(&set, encoder);
+        }
+    }
+    if input.rules.same_country_only {
+        exclude_pairs(input.cross_country_pairs().into_iter(), encoder);
+    }
+    include_pairs(input.whitelist.iter().cloned(), encoder);
+
+    // Exclude the pairs to avoid after the latest year.
+    if let Some(latest) = input.history.iter().max_by_key(|solution| solution.year) {
+        exclude_pairs(
+            latest.avoid_next.iter().filter(|p| no_charity(p)).cloned(),
+            encoder,
+        );
+    }
+
+    // Exclude historical pairs: from the most recent solutions if there's a
+    // lookback, otherwise from those that say to.
+    let mut history: Vec<&Solution> = input.history.iter().collect();
+    match input.history_lookback {
+        Some(lookback) => {
+            history.sort_by_key(|sol| Reverse(sol.year));
+            history.truncate(lookback as usize);
+        }
+        None => history.retain(|sol| sol.exclude_pairs),
+    }
+    debug!("Excluding the pairs of {} years of history.", history.len());
+    for solution in history {
+        exclude_pairs(
+            solution.pairs.iter().filter(|p| no_charity(p)).cloned(),
+            encoder,
+        );
+    }
+
+    if let Some(rule) = input.rules.gift_back {
+        let pairs = gift_back_pairs(&input.history, rule);
+        exclude_pairs(pairs.into_iter().filter(no_charity), encoder);
+    }
+}
+
+/// The encoding of the people and the core rules. It doesn't change from year
+/// to year, so it can be saved once and loaded instead of encoded every run.
+#[derive(Deserialize, Serialize)]
+struct BaseEncoding {
+    people: Vec<String>,
+    #[serde(default)]
+    allow_mutual: bool,
+    #[serde(default)]
+    gifts_per_person: Option<usize>,
+    clauses: Vec<Vec<i32>>,
+    varmap: VarMap<Pair<String>>,
+}
+
+/// A backend that only records its clauses.
+#[derive(Default)]
+struct Clauses(Vec<Vec<i32>>);
+
+impl Backend for Clauses {
+    fn add_clause<I>(&mut self, lits: I)
+    where
+        I: Iterator<Item = i32>,
+    {
+        self.0.push(lits.collect());
+    }
+}
+
+impl BaseEncoding {
+    pub fn new(people: Vec<String>, allow_mutual: bool, gifts_per_person: Option<usize>) -> Self {
+        let mut encoder = Encoder::<Pair<String>, Clauses>::new();
+        let gifts = gifts_per_person.unwrap_or(1);
+        encode_secret_santa_rules(&people, allow_mutual, gifts, &mut encoder);
+        BaseEncoding {
+            people,
+            allow_mutual,
+            gifts_per_person,
+            clauses: encoder.backend.0,
+            varmap: encoder.varmap,
+        }
+    }
+
+    /// Return an encoder with the base clauses already added.
+    fn into_encoder<B: Backend + Default>(self) -> Encoder<Pair<String>, B> {
+        let mut encoder = Encoder::<Pair<String>, B>::new();
+        for clause in self.clauses {
+            encoder.backend.add_clause(clause.into_iter());
+        }
+        encoder.varmap = self.varmap;
+        encoder
+    }
+}
+
+/// Return an encoder with the core rules for the input's people. They're
+/// loaded from the base encoding file if it has the same people,
+/// `allow_mutual`, and `gifts_per_person`. Otherwise they're encoded and saved
+/// to it for next time, with a warning if the file couldn't be read.
+fn base_encoder<B: Backend + Default>(input: &Input, path: &Path) -> Encoder<Pair<String>, B> {
+    let names = input.names();
+    match std::fs::read_to_string(path).map(|contents| ron::de::from_str::<BaseEncoding>(&contents))
+    {
+        Ok(Ok(base))
+            if base.people == names
+                && base.allow_mutual == input.allow_mutual
+                && base.gifts_per_person == input.gifts_per_person =>
+        {
+            return base.into_encoder();
+        }
+        // It's for other people or rules.
+        Ok(Ok(_)) => {}
+        Ok(Err(e)) => warn!(
+            "Base encoding '{}' is corrupt; encoding anew: {e}",
+            path.display()
+        ),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => warn!(
+            "Base encoding '{}' can't be read; encoding anew: {e}",
+            path.display()
+        ),
+    }
+    let base = BaseEncoding::new(names, input.allow_mutual, input.gifts_per_person);
+    if let Err(e) = std::fs::write(path, ron::ser::to_string(&base).unwrap()) {
+        warn!("Base encoding '{}' can't be written: {e}", path.display());
+    }
+    base.into_encoder()
+}
+
+/// A SAT backend to draw with: a satoxid `Solver` that can also prefer the
+/// encouraged pairs.
+trait DrawBackend: Solver + Default {
+    /// Solve preferring the encouraged pairs.
+    fn solve_encouraged(
+        encoder: &mut Encoder<Pair<String>, Self>,
+        encouraged: &[Pair<String>],
+    ) -> Option<Vec<Pair<String>>>;
+}
+
+impl DrawBackend for cadical::Solver {
+    /// CaDiCaL has no phase hints, so the encouraged pairs are assumed true
+    /// and any assumptions that make the problem unsatisfiable are dropped
+    /// until it's solvable. Assumptions aren't part of satoxid's `Solver`
+    /// trait, so this is CaDiCaL's own.
+    fn solve_encouraged(
+        encoder: &mut Encoder<Pair<String>, Self>,
+        encouraged: &[Pair<String>],
+    ) -> Option<Vec<Pair<String>>> {
+        let mut assumptions: Vec<i32> = encouraged
+            .iter()
+            .map(|pair| encoder.varmap.add_var(pair.clone()))
+            .collect();
+        loop {
+            match encoder.backend.solve_with(assumptions.iter().copied())? {
+                true => break,
+                false => {
+                    let count = assumptions.len();
+                    assumptions.retain(|&lit| !encoder.backend.failed(lit));
+                    if assumptions.len() == count {
+                        // Unsatisfiable without any assumptions.
+                        return None;
+                    }
+                }
+            }
+        }
+        Some(
+            (1..=encoder.backend.max_variable())
+                .filter(|&var| encoder.backend.value(var) == Some(true))
+                .filter_map(|var| encoder.varmap.lookup(var))
+                .map(|lit| lit.unwrap())
+                .collect(),
+        )
+    }
+}
+
+/// Find up to 100 independent solutions to the secret santa problem.
+pub fn solve(input: &Input) -> Vec<Vec<Pair<String>>> {
+    solve_variety(input, Variety::Exact)
+}
+
+/// Find up to 100 solutions, excluding each found by the variety.
+pub fn solve_variety(input: &Input, variety: Variety) -> Vec<Vec<Pair<String>>> {
+    solve_from(
+        input,
+        rules_encoder::<cadical::Solver>(input),
+        variety,
+        MAX_SOLUTIONS,
+        &mut Timings::default(),
+    )
+}
+
+/// Return an encoder with the core rules for the input's people.
+fn rules_encoder<B: Backend + Default>(input: &Input) -> Encoder<Pair<String>, B> {
+    let mut encoder = Encoder::new();
+    encode_secret_santa_rules(
+        &input.names(),
+        input.allow_mutual,
+        input.gifts_each(),
+        &mut encoder,
+    );
+    encoder
+}
+
+/// Find up to `max` solutions starting from the core rules, stopping early
+/// when there are no more.
+fn solve_from<B: DrawBackend>(
+    input: &Input,
+    mut encoder: Encoder<Pair<String>, B>,
+    variety: Variety,
+    max: usize,
+    timings: &mut Timings,
+) -> Vec<Vec<Pair<String>>> {
+    timings.time("encoding", || encode_constraints(input, &mut encoder));
+
+    // Pairs with people not present would be unconstrained.
+    let present = |name: &String| input.people.iter().any(|p| &p.name == name);
+    let encouraged: Vec<Pair<String>> = input
+        .encouraged
+        .iter()
+        .filter(|pair| present(&pair.giver) && present(&pair.receiver))
+        .cloned()
+        .collect();
+
+    let mut solutions = vec![];
+
+    for i in 0..max {
+        let found = timings.time("solving", || {
+            if encouraged.is_empty() {
+                encoder.solve().map(extract_pos)
+            } else {
+                B::solve_encouraged(&mut encoder, &encouraged)
+            }
+        });
+        timings.solves += 1;
+        let Some(pairs) = found else {
+            debug!("Solve {}: no more solutions.", i + 1);
+            break;
+        };
+        debug!(
+            "Solve {}: found a solution of {} pairs.",
+            i + 1,
+            pairs.len()
+        );
+        // Two different kinds of exclusions can be done to find multiple
+        // solutions:
+        //
+        // 1) This excludes_some_pairs ensures you can't repeat the same
+        //    thing but variations are allowed.
+        //
+        // ```
+        // exclude_some_pairs(pairs.iter().cloned(), &mut encoder);
+        // ```
+        //
+        // 2) This exlude_pairs ensures none of the pairings found are repeated.
+        //
+        // ```
+        // exclude_pairs(pairs.iter().cloned(), &mut encoder);
+        // ````
+        //
+        // We do #2 by default to ensure variety when choosing a random
+        // one; `--variety variations` does #1.
+        match variety {
+            Variety::Exact => exclude_pairs(pairs.iter().cloned(), &mut encoder),
+            Variety::Variations => exclude_some_pairs(pairs.iter().cloned(), &mut encoder),
+        }
+        solutions.push(pairs);
+    }
+    solutions
+}
+
+/// Return who each person could give to in some solution.
+fn feasible_receivers(input: &Input) -> BTreeMap<String, Vec<String>> {
+    let mut encoder = CadicalEncoder::new();
+    encode_input(input, &mut encoder);
+    let mut feasible = BTreeMap::new();
+    for giver in &input.people {
+        let receivers = input
+            .people
+            .iter()
+            .filter(|receiver| {
+                let pair = Pair::new(giver.name.clone(), receiver.name.clone());
+                let lit = encoder.varmap.add_var(pair);
+                encoder.backend.solve_with(std::iter::once(lit)) == Some(true)
+            })
+            .map(|receiver| receiver.name.clone())
+            .collect();
+        feasible.insert(giver.name.clone(), receivers);
+    }
+    feasible
+}
+
+/// Return the pair's giver and receiver followed by its reason, if any.
+fn with_reason(pair: &Pair<String>, verb: &str) -> String {
+    let mut line = format!("{} {verb} {}", pair.giver, pair.receiver);
+    if let Some(until) = pair.until_year {
+        write!(line, " until {until}").unwrap();
+    }
+    if let Some(ref reason) = pair.reason {
+        write!(line, ": {reason}").unwrap();
+    }
+    line
+}
+
+/// Return the whitelisted and blacklisted pairs and why they're there.
+pub fn explain(input: &Input) -> String {
+    let mut out = String::new();
+    for (title, pairs, verb) in [
+        ("Whitelist", &input.whitelist, "must give to"),
+        ("Blacklist", &input.blacklist, "may not give to"),
+    ] {
+        if pairs.is_empty() {
+            continue;
+        }
+        writeln!(out, "{title}:").unwrap();
+        for pair in pairs {
+            writeln!(out, "  {}", with_reason(pair, verb)).unwrap();
+        }
+    }
+    out
+}
+
+/// Write a CSV row per person summarizing their constraints and history.
+pub fn people_report(input: &Input, writer: impl std::io::Write) -> csv::Result<()> {
+    let mut writer = csv::Writer::from_writer(writer);
+    writer.write_record([
+        "name",
+        "households",
+        "attributes",
+        "blacklist",
+        "whitelist",
+        "feasible_receivers",
+        "past_receivers",
+        "past_santas",
+        "reasons",
+    ])?;
+    let feasible = feasible_receivers(input);
+    let households = input.households();
+    for person in &input.people {
+        let name = &person.name;
+        let involves = |pair: &&Pair<String>| &pair.giver == name || &pair.receiver == name;
+        let in_households: Vec<String> = households
+            .iter()
+            .filter(|household| household.contains(name))
+            .map(|household| household.join(" "))
+            .collect();
+        let attributes: Vec<String> = person
+            .attributes
+            .iter()
+            .map(|(key, value)| format!("{key}={value}"))
+            .collect();
+        let receivers: Vec<String> = receivers_for(name, input).collect();
+        let givers: Vec<String> = givers_for(name, input).collect();
+        let reasons: Vec<String> = input
+            .whitelist
+            .iter()
+            .chain(&input.blacklist)
+            .filter(involves)
+            .filter(|pair| pair.reason.is_some())
+            .map(|pair| with_reason(pair, "->"))
+            .collect();
+        writer.write_record([
+            name.clone(),
+            in_households.join("; "),
+            attributes.join("; "),
+            input.blacklist.iter().filter(involves).count().to_string(),
+            input.whitelist.iter().filter(involves).count().to_string(),
+            feasible[name].len().to_string(),
+            receivers.join("; "),
+            givers.join("; "),
+            reasons.join("; "),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Return how many independent solutions the search finds for the input or
+/// each of its groups, with the label. None found means it's infeasible.
+pub fn feasibility(input: &Input, timings: &mut Timings) -> Vec<(Option<String>, usize)> {
+    input
+        .draws()
+        .into_iter()
+        .map(|(label, draw)| {
+            let encoder = timings.time("encoding", || rules_encoder::<cadical::Solver>(&draw));
+            let count = solve_from(&draw, encoder, Variety::Exact, MAX_SOLUTIONS, timings).len();
+            (label, count)
+        })
+        .collect()
+}
+
+/// Return the input with only the core rules and then with each layer of
+/// constraints added in turn, with the layer's name.
+fn constraint_layers(input: &Input) -> Vec<(&'static str, Input)> {
+    let mut layer = Input {
+        people: input
+            .people
+            .iter()
+            .map(|p| Person {
+                household: None,
+                exclude_givers: vec![],
+                ..p.clone()
+            })
+            .collect(),
+        whitelist: vec![],
+        blacklist: vec![],
+        blacklist_sets: vec![],
+        couples: vec![],
+        history: vec![],
+        history_lookback: None,
+        different_cycle_sets: vec![],
+        rules: Rules {
+            gift_back: None,
+            forbid_same_surname: false,
+            min_cycle_length: None,
+            min_displacement: None,
+            same_country_only: false,
+            ..input.rules.clone()
+        },
+        ..input.clone()
+    };
+    let mut layers = vec![("the core rules", layer.clone())];
+    layer.people = input.people.clone();
+    layer.blacklist = input.blacklist.clone();
+    layer.blacklist_sets = input.blacklist_sets.clone();
+    layer.couples = input.couples.clone();
+    layer.rules.forbid_same_surname = input.rules.forbid_same_surname;
+    layers.push(("the blacklist and households", layer.clone()));
+    layer.whitelist = input.whitelist.clone();
+    layers.push(("the whitelist", layer.clone()));
+    layer.history = input.history.clone();
+    layer.history_lookback = input.history_lookback;
+    layer.rules.gift_back = input.rules.gift_back;
+    layers.push(("the history", layer.clone()));
+    layer.different_cycle_sets = input.different_cycle_sets.clone();
+    layer.rules.min_cycle_length = input.rules.min_cycle_length;
+    layer.rules.min_displacement = input.rules.min_displacement;
+    layers.push(("the cycle and displacement rules", layer.clone()));
+    layer.rules.same_country_only = input.rules.same_country_only;
+    layers.push(("the same country rule", layer));
+    layers
+}
+
+/// Return the first layer of constraints that makes the input infeasible.
+/// Each layer is solved with a fresh encoder.
+pub fn infeasible_layer(input: &Input) -> Option<&'static str> {
+    constraint_layers(input)
+        .into_iter()
+        .find_map(|(name, layer)| {
+            let mut encoder = CadicalEncoder::new();
+            encode_input(&layer, &mut encoder);
+            encoder.solve().is_none().then_some(name)
+        })
+}
+
+/// Return a hint for what to relax when there are no solutions.
+fn infeasible_hint(input: &Input) -> String {
+    match infeasible_layer(input) {
+        Some("the core rules") => {
+            String::from("\nThe core rules alone have no solution; are there at least 3 people?")
+        }
+        Some(layer) => format!("\nIt becomes unsolvable with {layer}; try relaxing those."),
+        None => String::new(),
+    }
+}
+
+/// Stop counting solutions at this many.
+pub const COUNT_CAP: usize = 10_000;
+
+/// Count the solutions up to the cap by blocking each one found. Return the
+/// count and whether it's exact, i.e., every solution was found.
+pub fn count_solutions(input: &Input, cap: usize) -> (usize, bool) {
+    let mut solutions = solutions(input);
+    let count = solutions.by_ref().take(cap).count();
+    (count, count < cap || solutions.next().is_none())
+}
+
+/// Yield every distinct solution, solving for each one only when it's
+/// asked for. Each is blocked once found, so none repeats.
+pub fn solutions(input: &Input) -> impl Iterator<Item = Vec<Pair<String>>> {
+    let mut encoder = CadicalEncoder::new();
+    encode_input(input, &mut encoder);
+    std::iter::from_fn(move || {
+        let pairs = encoder.solve().map(extract_pos)?;
+        exclude_some_pairs(pairs.iter().cloned(), &mut encoder);
+        Some(pairs)
+    })
+}
+
+/// Return true if no solution other than this one exists.
+fn is_unique(input: &Input, pairs: &[Pair<String>]) -> bool {
+    let mut encoder = CadicalEncoder::new();
+    encode_input(input, &mut encoder);
+    exclude_some_pairs(pairs.iter().cloned(), &mut encoder);
+    encoder.solve().is_none()
+}
+
+/// Confirm the solver could have produced the solution: with its pairs
+/// included one at a time, the encoding stays satisfiable. Return the first
+/// inconsistency otherwise. The rules checked at selection time aren't
+/// encoded, so they aren't checked.
+pub fn check_solution(input: &Input, solution: &Solution) -> Result<(), String> {
+    if let Some(problem) = solution
+        .permutation_problems(input.gifts_each())
+        .into_iter()
+        .next()
+    {
+        return Err(format!("{problem}."));
+    }
+    for pair in &solution.pairs {
+        for name in [&pair.giver, &pair.receiver] {
+            if !input.people.iter().any(|p| &p.name == name) {
+                return Err(format!("Named '{name}' not found in people set."));
+            }
+        }
+    }
+    for (label, draw) in input.draws() {
+        let label = label.map(|l| format!(" in '{l}'")).unwrap_or_default();
+        let names = draw.names();
+        let pairs: Vec<&Pair<String>> = solution
+            .pairs
+            .iter()
+            .filter(|pair| names.contains(&pair.giver))
+            .collect();
+        for pair in &pairs {
+            if !names.contains(&pair.receiver) {
+                return Err(format!(
+                    "'{}' gives to '{}' who isn't drawn with them{label}.",
+                    pair.giver, pair.receiver
+                ));
+            }
+        }
+        // The charity's pair isn't a gift, so it's left out of solutions.
+        if let Some(name) = names
+            .iter()
+            .find(|name| !draw.is_charity(name) && !pairs.iter().any(|p| &&p.giver == name))
+        {
+            return Err(format!("'{name}' gives to no one{label}."));
+        }
+        let mut encoder = CadicalEncoder::new();
+        encode_input(&draw, &mut encoder);
+        for pair in pairs {
+            include_pairs(std::iter::once(pair.clone()), &mut encoder);
+            if encoder.solve().is_none() {
+                return Err(format!(
+                    "'{}' giving to '{}' is inconsistent with the constraints{label}.",
+                    pair.giver, pair.receiver
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Find a solution that keeps as many of the prior pairs as possible.
+///
+/// The prior pairs are a soft `include_pairs`: we require all of them and
+/// relax the requirement one pair at a time until the problem is solvable.
+fn solve_near(input: &Input, prior: &[Pair<String>]) -> Option<Vec<Pair<String>>> {
+    // Pairs with people no longer present would be unconstrained.
+    let present = |name: &String| input.people.iter().any(|p| &p.name == name);
+    let prior: Vec<Pair<String>> = prior
+        .iter()
+        .filter(|pair| present(&pair.giver) && present(&pair.receiver))
+        .cloned()
+        .collect();
+    (0..=prior.len()).rev().find_map(|k| {
+        let mut encoder = CadicalEncoder::new();
+        encode_input(input, &mut encoder);
+        encoder.add_constraint(AtLeastK {
+            k: k as u32,
+            lits: prior.iter().cloned(),
+        });
+        encoder.solve().map(extract_pos)
+    })
+}
+
+/// Find a solution that earns the most preference points.
+///
+/// Each point is a literal: a preferred pair repeated for its weight or an
+/// avoided pair negated for its. Every solution found requires the next to
+/// satisfy more of them until none can; the last one found is the best.
+fn solve_preferred(input: &Input) -> Option<Vec<Pair<String>>> {
+    // Pairs with people not present would be unconstrained.
+    let present = |name: &String| input.people.iter().any(|p| &p.name == name);
+    let lits: Vec<Lit<Pair<String>>> = input
+        .preferences
+        .iter()
+        .filter(|(pair, _)| present(&pair.giver) && present(&pair.receiver))
+        .flat_map(|(pair, weight)| {
+            let lit = if *weight > 0 {
+                Lit::Pos(pair.clone())
+            } else {
+                Lit::Neg(pair.clone())
+            };
+            std::iter::repeat_n(lit, weight.unsigned_abs() as usize)
+        })
+        .collect();
+    let mut encoder = CadicalEncoder::new();
+    encode_input(input, &mut encoder);
+    let mut best = encoder.solve().map(extract_pos)?;
+    loop {
+        let satisfied = lits
+            .iter()
+            .filter(|lit| match lit {
+                Lit::Pos(pair) => best.contains(pair),
+                Lit::Neg(pair) => !best.contains(pair),
+            })
+            .count();
+        if satisfied == lits.len() {
+            return Some(best);
+        }
+        encoder.add_constraint(AtLeastK {
+            k: satisfied as u32 + 1,
+            lits: lits.iter().cloned(),
+        });
+        match encoder.solve() {
+            Some(model) => best = extract_pos(model),
+            None => return Some(best),
+        }
+    }
+}
+
+/// Return how many pairs aren't in any year of history.
+fn novelty_score(pairs: &[Pair<String>], history: &[Solution]) -> usize {
+    pairs
+        .iter()
+        .filter(|pair| !history.iter().any(|s| s.pairs.contains(pair)))
+        .count()
+}
+
+/// Return how the pairs differ from the prior ones, giver by giver, and how
+/// many are unchanged.
+pub fn solution_diff(prior: &[Pair<String>], pairs: &[Pair<String>]) -> String {
+    let receiver = |pairs: &[Pair<String>], giver: &str| {
+        pairs
+            .iter()
+            .find(|p| p.giver == giver)
+            .map(|p| p.receiver.clone())
+    };
+    let mut diff = String::new();
+    let mut unchanged = 0;
+    for pair in pairs {
+        match receiver(prior, &pair.giver) {
+            Some(ref before) if before == &pair.receiver => unchanged += 1,
+            Some(before) => writeln!(
+                diff,
+                "Changed: {} gives to {} instead of {}",
+                pair.giver, pair.receiver, before
+            )
+            .unwrap(),
+            None => writeln!(diff, "Added: {} gives to {}", pair.giver, pair.receiver).unwrap(),
+        }
+    }
+    for pair in prior {
+        if receiver(pairs, &pair.giver).is_none() {
+            writeln!(diff, "Removed: {} gave to {}", pair.giver, pair.receiver).unwrap();
+        }
+    }
+    writeln!(diff, "Unchanged: {unchanged} pairs").unwrap();
+    diff
+}
+
+/// Return how many pairs two solutions share.
+fn overlap(pairs: &[Pair<String>], prior: &[Pair<String>]) -> usize {
+    pairs.iter().filter(|pair| prior.contains(pair)).count()
+}
+
+/// The assignment drawn for the whole input or one of its groups.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, Default)]
+pub struct Draw {
+    pub label: Option<String>,
+    pub pairs: Vec<Pair<String>>,
+    /// Who each giver gives to instead if their receiver declines.
+    #[serde(default)]
+    pub fallbacks: Vec<Pair<String>>,
+    /// What the draw found and kept on the way to its choice, for the caller
+    /// to print.
+    #[serde(skip)]
+    pub report: Vec<String>,
+}
+
+impl Draw {
+    /// Return the giver's fallback receiver.
+    fn fallback_for(&self, giver: &str) -> Option<&str> {
+        self.fallbacks
+            .iter()
+            .find(|pair| pair.giver == giver)
+            .map(|pair| pair.receiver.as_str())
+    }
+}
+
+/// Return a fallback pair for each giver: a different receiver the giver could
+/// have in some other valid solution.
+fn fallbacks(input: &Input, pairs: &[Pair<String>]) -> Vec<Pair<String>> {
+    pairs
+        .iter()
+        .filter_map(|pair| {
+            let mut encoder = CadicalEncoder::new();
+            encode_input(input, &mut encoder);
+            exclude_pairs(std::iter::once(pair.clone()), &mut encoder);
+            let other = encoder.solve().map(extract_pos)?;
+            other.into_iter().find(|p| p.giver == pair.giver)
+        })
+        .collect()
+}
+
+/// Somewhere to get the people from instead of the input file.
+///
+/// Implement this to pull the roster from a directory. The `people-exec`
+/// feature provides one that runs a command.
+pub trait PeopleSource {
+    fn people(&self) -> std::io::Result<Vec<Person>>;
+}
+
+/// People listed in a CSV file.
+pub struct CsvPeople {
+    pub path: PathBuf,
+}
+
+impl PeopleSource for CsvPeople {
+    fn people(&self) -> std::io::Result<Vec<Person>> {
+        parse_people_csv(std::fs::File::open(&self.path)?)
+    }
+}
+
+/// People printed as CSV by a command.
+#[cfg(feature = "people-exec")]
+pub struct ExecPeople {
+    pub command: String,
+}
+
+#[cfg(feature = "people-exec")]
+impl PeopleSource for ExecPeople {
+    fn people(&self) -> std::io::Result<Vec<Person>> {
+        let args: Vec<&str> = self.command.split_whitespace().collect();
+        let output = run_fun!($[args])?;
+        parse_people_csv(output.as_bytes())
+    }
+}
+
+/// Return an input with only the people of a `name,email` list, one person
+/// per line. A `name,email` header and blank lines are skipped.
+pub fn skeleton_input(list: &str) -> Result<Input, String> {
+    let mut people = vec![];
+    for (i, line) in list.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || (i == 0 && line.eq_ignore_ascii_case("name,email")) {
+            continue;
+        }
+        let Some((name, email)) = line.rsplit_once(',') else {
+            return Err(format!("Line {} has no 'name,email': '{line}'.", i + 1));
+        };
+        people.push(Person {
+            name: name.trim().to_string(),
+            email: email.trim().to_string(),
+            ..Default::default()
+        });
+    }
+    let input = Input {
+        people,
+        ..Default::default()
+    };
+    input.validate().map_err(|e| e.to_string())?;
+    Ok(input)
+}
+
+/// Parse people from CSV with `name` and `email` columns. Other non-empty
+/// columns become attributes.
+fn parse_people_csv(reader: impl std::io::Read) -> std::io::Result<Vec<Person>> {
+    let invalid = |e: String| std::io::Error::new(std::io::ErrorKind::InvalidData, e);
+    let mut reader = csv::Reader::from_reader(reader);
+    let headers = reader.headers()?.clone();
+    for column in ["name", "email"] {
+        if !headers.iter().any(|h| h == column) {
+            return Err(invalid(format!("People CSV has no '{column}' column.")));
+        }
+    }
+    let mut people = vec![];
+    for record in reader.records() {
+        let mut person = Person::default();
+        for (header, value) in headers.iter().zip(record?.iter()) {
+            match header {
+                "name" => person.name = value.to_string(),
+                "email" => person.email = value.to_string(),
+                _ if value.is_empty() => {}
+                _ => {
+                    person
+                        .attributes
+                        .insert(header.to_string(), value.to_string());
+                }
+            }
+        }
+        people.push(person);
+    }
+    Ok(people)
+}
+
+/// Write the file unless it exists and we're not forced to.
+pub fn write_new(path: &Path, contents: &str, force: bool) -> std::io::Result<()> {
+    let file = if force {
+        std::fs::File::create(path)
+    } else {
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .map_err(|e| match e.kind() {
+                std::io::ErrorKind::AlreadyExists => std::io::Error::new(
+                    e.kind(),
+                    format!(
+                        "'{}' already exists. Use --force to overwrite it.",
+                        path.display()
+                    ),
+                ),
+                _ => e,
+            })
+    };
+    file?.write_all(contents.as_bytes())
+}
+
+/// Return the seed in the `SECRET_SANTA_SEED` environment variable, or an
+/// error if it isn't a non-negative integer.
+pub fn env_seed() -> Result<Option<u64>, String> {
+    let Ok(seed) = std::env::var("SECRET_SANTA_SEED") else {
+        return Ok(None);
+    };
+    seed.trim()
+        .parse()
+        .map(Some)
+        .map_err(|_| format!("SECRET_SANTA_SEED must be a non-negative integer, not '{seed}'."))
+}
+
+/// Return the random number generator for choosing solutions: seeded for
+/// reproducible runs, otherwise the thread's.
+pub fn seeded_rng(seed: Option<u64>) -> Box<dyn RngCore> {
+    match seed {
+        Some(seed) => Box::new(StdRng::seed_from_u64(seed)),
+        None => Box::new(rand::thread_rng()),
+    }
+}
+
+/// Chooses among the solutions left after the rules and preferences.
+pub trait Selector {
+    /// Return the index of the chosen solution. There's at least one.
+    fn select(&mut self, solutions: &[Vec<Pair<String>>]) -> usize;
+}
+
+/// Chooses at random, reproducibly if the generator is seeded.
+pub struct RandomSelector<R: RngCore>(pub R);
+
+impl<R: RngCore> Selector for RandomSelector<R> {
+    fn select(&mut self, solutions: &[Vec<Pair<String>>]) -> usize {
+        (0..solutions.len()).choose(&mut self.0).unwrap()
+    }
+}
+
+/// Return true if the path ends in `.json`.
+fn is_json(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "json")
+}
+
+/// Return true if the path is `-`, meaning stdin.
+fn is_stdin(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+/// Parse the input as JSON if the file ends in `.json`, otherwise as RON.
+/// Stdin has no extension, so it's JSON if it starts with `{`.
+pub fn parse_input(path: &Path, bytes: &[u8]) -> Result<Input, InputError> {
+    let error = |error: String| InputError::Parse {
+        path: path.to_path_buf(),
+        error,
+    };
+    let stdin = is_stdin(path);
+    if is_json(path) || (stdin && bytes.trim_ascii_start().starts_with(b"{")) {
+        return serde_json::from_slice(bytes).map_err(|e| error(e.to_string()));
+    }
+    if !stdin && path.extension().is_none_or(|ext| ext != "ron") {
+        warn!(
+            "'{}' is neither .ron nor .json; reading it as RON.",
+            path.display()
+        );
+    }
+    ron::de::from_bytes(bytes).map_err(|e| error(e.to_string()))
+}
+
+/// Write the input in the same format `parse_input` reads from the path.
+pub fn serialize_input(path: &Path, input: &Input) -> String {
+    if is_json(path) {
+        serde_json::to_string_pretty(input).unwrap()
+    } else {
+        ron::ser::to_string_pretty(input, PrettyConfig::default()).unwrap()
+    }
+}
+
+/// Read the input file, validate it, and apply this year's constraints. The
+/// people come from the source and the rules from the profile if they're
+/// given.
+pub fn read_input(
+    path: &Path,
+    year: u16,
+    source: Option<&dyn PeopleSource>,
+    profile: Option<&str>,
+    timings: &mut Timings,
+) -> Result<(Vec<u8>, Input), InputError> {
+    let start = Instant::now();
+    let read_error = |e: std::io::Error| InputError::Read {
+        path: path.to_path_buf(),
+        error: e.to_string(),
+    };
+    let bytes = if is_stdin(path) {
+        let mut bytes = vec![];
+        std::io::stdin()
+            .read_to_end(&mut bytes)
+            .map_err(read_error)?;
+        bytes
+    } else {
+        std::fs::read(path).map_err(read_error)?
+    };
+
+    let mut input = parse_input(path, &bytes)?;
+    if let Some(source) = source {
+        input.people = source
+            .people()
+            .map_err(|e| InputError::People(e.to_string()))?;
+    }
+    timings.add("parsing", start);
+
+    let start = Instant::now();
+    if let Some(profile) = profile {
+        input.use_profile(profile)?;
+    }
+    input.validate()?;
+    input.for_year(year)?;
+
+    input.history.sort_by_key(|sol| Reverse(sol.year));
+    timings.add("validation", start);
+    Ok((bytes, input))
+}
+
+/// Confirm the pairs are a valid assignment of the people: each gives and
+/// receives `gifts` times, never to themselves. It keeps a mistake in the
+/// encoding from reaching anyone's inbox.
+pub fn verify_solution(
+    people: &[String],
+    gifts: usize,
+    pairs: &[Pair<String>],
+) -> Result<(), String> {
+    for pair in pairs {
+        if pair.giver == pair.receiver {
+            return Err(format!("'{}' gives to themselves!", pair.giver));
+        }
+        for name in [&pair.giver, &pair.receiver] {
+            if !people.contains(name) {
+                return Err(format!(
+                    "'{name}' is in the solution but not the people set!"
+                ));
+            }
+        }
+    }
+    for name in people {
+        let gives = pairs.iter().filter(|p| &p.giver == name).count();
+        if gives != gifts {
+            return Err(format!(
+                "'{name}' gives {gives} times instead of {}!",
+                times(gifts)
+            ));
+        }
+        let receives = pairs.iter().filter(|p| &p.receiver == name).count();
+        if receives != gifts {
+            return Err(format!(
+                "'{name}' receives {receives} times instead of {}!",
+                times(gifts)
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Find up to `max` solutions for a draw with the backend, starting from the
+/// base encoding if there is one.
+fn solve_draw<B: DrawBackend>(
+    draw: &Input,
+    options: &DrawOptions,
+    max: usize,
+    timings: &mut Timings,
+) -> Vec<Vec<Pair<String>>> {
+    let encoder = timings.time("encoding", || match options.base_encoding {
+        Some(ref path) => base_encoder::<B>(draw, path),
+        None => rules_encoder::<B>(draw),
+    });
+    solve_from(draw, encoder, options.variety, max, timings)
+}
+
+/// Solve and choose a solution for the input or each of its groups.
+pub fn draw(
+    input: &Input,
+    options: &DrawOptions,
+    prior: Option<&Solution>,
+    selector: &mut dyn Selector,
+    audit: &mut AuditEntry,
+    timings: &mut Timings,
+) -> Result<Vec<Draw>, String> {
+    let mut draws = vec![];
+    for (label, draw) in input.draws() {
+        let max = options
+            .require_count
+            .map_or(options.max_solutions, |count| {
+                count.max(options.max_solutions)
+            });
+        let mut solutions = match options.solver {
+            SatSolver::Cadical => solve_draw::<cadical::Solver>(&draw, options, max, timings),
+        };
+        audit.solutions += solutions.len();
+        let start = Instant::now();
+        let mut report = vec![format!("Solved with {}.", options.solver)];
+
+        if solutions.is_empty() {
+            return Err(match label {
+                Some(label) => format!(
+                    "No secret santa solutions found for '{label}'!{}",
+                    infeasible_hint(&draw)
+                ),
+                None => format!("No secret santa solutions found!{}", infeasible_hint(&draw)),
+            });
+        }
+
+        if let Some(count) = options.require_count {
+            if solutions.len() < count {
+                return Err(format!(
+                    "Only {} solutions found but {count} are required!",
+                    solutions.len()
+                ));
+            }
+        }
+
+        if solutions.len() == 1 && is_unique(&draw, &solutions[0]) {
+            report.push(String::from(
+                "Found the only solution possible given the constraints.",
+            ));
+        } else if solutions.len() < max {
+            report.push(format!(
+                "Found all {} independent solutions; there are no more. Choosing one.",
+                solutions.len()
+            ));
+        } else {
+            report.push(format!(
+                "Found {} independent solutions. Choosing one.",
+                solutions.len()
+            ));
+        }
+
+        // The best solutions for the preferences and the prior go through
+        // the same filters as those found.
+        if !draw.preferences.is_empty() {
+            solutions.extend(solve_preferred(&draw));
+        }
+        if let Some(prior) = prior {
+            solutions.extend(solve_near(&draw, &prior.pairs));
+        }
+
+        // Balance is checked among the solutions found rather than encoded,
+        // so a hard to balance group may need more of them.
+        if let Some(ref attribute) = draw.rules.balance_attribute {
+            solutions.retain(|pairs| cycles_balanced(pairs, &draw.people, attribute));
+            if solutions.is_empty() {
+                return Err(format!(
+                    "No solutions found with '{attribute}' balanced in every cycle!"
+                ));
+            }
+        }
+
+        if let Some(lookback) = draw.rules.no_repeat_cycles {
+            solutions.retain(|pairs| !repeats_cycle(pairs, &draw.history, lookback));
+            if solutions.is_empty() {
+                return Err(String::from(
+                    "No solutions found that don't repeat a cycle from history!",
+                ));
+            }
+        }
+
+        if draw.rules.forbid_reversal {
+            let max = draw.rules.max_reversed_pairs;
+            solutions.retain(|pairs| {
+                !reverses_latest(&draw.without_charities(pairs), &draw.history, max)
+            });
+            if solutions.is_empty() {
+                return Err(String::from(
+                    "No solutions found that don't reverse last year's!",
+                ));
+            }
+        }
+
+        if draw.rules.require_cross_group_cycles {
+            let households = draw.households();
+            solutions.retain(|pairs| !has_island(pairs, &draw.people, &households));
+            if solutions.is_empty() {
+                return Err(String::from(
+                    "No solutions found with every cycle crossing households and attributes!",
+                ));
+            }
+        }
+
+        if let Some(count) = draw.rules.exact_cycles {
+            solutions.retain(|pairs| cycles(pairs).len() == count);
+            if solutions.is_empty() {
+                return Err(format!("No solutions found with exactly {count} cycles!"));
+            }
+        }
+
+        if !draw.preferences.is_empty() {
+            retain_best(&mut solutions, |pairs| {
+                -(draw.preference_points(pairs) as f64)
+            });
+            report.push(format!(
+                "Kept {} solutions with the most preference points.",
+                solutions.len()
+            ));
+        }
+
+        if let Some(prior) = prior {
+            retain_best(&mut solutions, |pairs| {
+                -(overlap(pairs, &prior.pairs) as f64)
+            });
+            report.push(format!(
+                "Kept {} solutions closest to the prior solution.",
+                solutions.len()
+            ));
+        }
+
+        if let Some(decay) = draw.rules.history_decay {
+            retain_best(&mut solutions, |pairs| {
+                history_penalty(pairs, &draw.history, decay)
+            });
+            report.push(format!(
+                "Kept {} solutions repeating the least recent history.",
+                solutions.len()
+            ));
+        }
+
+        if !draw.distances.is_empty() {
+            retain_best(&mut solutions, |pairs| draw.total_distance(pairs) as f64);
+            report.push(format!(
+                "Kept {} solutions with the least total distance.",
+                solutions.len()
+            ));
+        }
+
+        match options.prefer {
+            Some(Preference::BalancedHouseholds) => {
+                let households = draw.households();
+                retain_best(&mut solutions, |pairs| {
+                    household_balance_penalty(pairs, &households)
+                });
+                report.push(format!(
+                    "Kept {} solutions with the most balanced household giving.",
+                    solutions.len()
+                ));
+            }
+            Some(Preference::NovelPairs) => {
+                retain_best(&mut solutions, |pairs| {
+                    -(novelty_score(pairs, &draw.history) as f64)
+                });
+                report.push(format!(
+                    "Kept {} solutions with the most pairs never drawn before.",
+                    solutions.len()
+                ));
+            }
+            None => {}
+        }
+
+        let chosen = selector.select(&solutions);
+        debug!("Chose solution {chosen} of {}.", solutions.len());
+        audit.chosen.push(chosen);
+        let pairs = solutions.swap_remove(chosen);
+        verify_solution(&draw.names(), draw.gifts_each(), &pairs)?;
+        if !draw.preferences.is_empty() {
+            report.push(format!(
+                "The chosen solution earns {} of {} preference points.",
+                draw.preference_points(&pairs),
+                draw.possible_points()
+            ));
+        }
+        let mut pairs = draw.gifts(pairs);
+
+        pairs.sort_by(|a, b| a.giver.cmp(&b.giver));
+        let fallbacks = if options.with_fallback {
+            let fallbacks = fallbacks(&draw, &pairs);
+            for pair in &pairs {
+                if !fallbacks.iter().any(|p| p.giver == pair.giver) {
+                    warn!("No fallback possible for '{}'.", pair.giver);
+                }
+            }
+            fallbacks
+        } else {
+            vec![]
+        };
+        draws.push(Draw {
+            label,
+            pairs,
+            fallbacks,
+            report,
+        });
+        timings.add("selection", start);
+    }
+    Ok(draws)
+}
+
+/// Add the draws to the history in the file as one year, replacing any
+/// already there for that year.
+pub fn save_history(path: &Path, year: u16, draws: &[Draw]) -> std::io::Result<()> {
+    let mut history: Vec<Solution> = match std::fs::read(path) {
+        Ok(bytes) => ron::de::from_bytes(&bytes).map_err(std::io::Error::other)?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => vec![],
+        Err(e) => return Err(e),
+    };
+    history.retain(|solution| solution.year != year);
+    history.push(Solution {
+        year,
+        exclude_pairs: true,
+        pairs: draws.iter().flat_map(|draw| draw.pairs.clone()).collect(),
+        ..Default::default()
+    });
+    let contents = ron::ser::to_string_pretty(&history, PrettyConfig::default()).unwrap();
+    std::fs::write(path, contents)
+}
+
+/// Compose the messages for every pair drawn.
+pub fn compose_messages(draws: &[Draw], input: &Input) -> Vec<Message> {
+    let mut msgs = vec![];
+    // Generate all the messages first to confirm there aren't any errors.
+    for draw in draws {
+        for pair in &draw.pairs {
+            let msg = compose_message(pair, input, draw).expect("Failed to compose message");
+            msgs.push(msg);
+        }
+    }
+    msgs
+}
+
+/// Return the organizer's checklist: the event's details, what to do, and
+/// who to expect. It lists the people but never their assignments.
+pub fn checklist(input: &Input) -> String {
+    let mut out = String::from("# Secret Santa Checklist\n\n");
+    writeln!(out, "- Participants: {}", input.people.len()).unwrap();
+    for (label, value) in [
+        ("Budget", &input.budget),
+        ("Date", &input.event_date),
+        ("Location", &input.event_location),
+    ] {
+        if let Some(value) = value {
+            writeln!(out, "- {label}: {value}").unwrap();
+        }
+    }
+    out.push_str("\n## To Do\n\n");
+    out.push_str("- [ ] Send the assignments\n");
+    out.push_str("- [ ] Remind everyone to bring their gift\n");
+    out.push_str("- [ ] Collect the gifts\n");
+    out.push_str("\n## Attendance\n\n");
+    for person in &input.people {
+        writeln!(out, "- [ ] {}", person.name).unwrap();
+    }
+    out
+}
+
+/// Return the message the giver will be sent.
+pub fn preview(name: &str, draws: &[Draw], input: &Input) -> Option<Message> {
+    draws.iter().find_map(|draw| {
+        let pair = draw.pairs.iter().find(|pair| pair.giver == name)?;
+        Some(compose_message(pair, input, draw).expect("Failed to compose message"))
+    })
+}
+
+/// Return the message as a dry run shows it, whole and set apart from the
+/// next one.
+pub fn dry_run_message(msg: &Message) -> String {
+    format!(
+        "----- To: {}\nSubject: {}\n\n{}----- End of message\n",
+        msg.email, msg.subject, msg.body
+    )
+}
+
+/// Summarize the messages of a dry run that has no delivery command.
+pub fn dry_run_summary(msgs: &[Message], verbose: bool) -> String {
+    let mut summary = format!(
+        "Composed {} messages; no delivery command given.",
+        msgs.len()
+    );
+    if verbose {
+        for msg in msgs {
+            summary.push_str(&format!("\n  {}", msg.email));
+        }
+    }
+    summary
+}
+
+/// Return the draws as a graphviz digraph with each cycle in its own color.
+pub fn to_dot(draws: &[Draw]) -> String {
+    const COLORS: [&str; 6] = ["red", "blue", "darkgreen", "orange", "purple", "brown"];
+    let mut dot = String::from("digraph secret_santa {\n");
+    let all_cycles = draws.iter().flat_map(|draw| cycles(&draw.pairs));
+    for (i, cycle) in all_cycles.enumerate() {
+        let color = COLORS[i % COLORS.len()];
+        for (j, giver) in cycle.iter().enumerate() {
+            let receiver = &cycle[(j + 1) % cycle.len()];
+            dot.push_str(&format!(
+                "    {:?} -> {:?} [color={}];\n",
+                giver, receiver, color
+            ));
+        }
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+/// Return the order to open the gifts in at the event, one line per cycle.
+/// Each person opens the gift from the one before them, and the first opens
+/// the gift from the last, so giving is revealed one step at a time.
+pub fn reveal_order(draws: &[Draw]) -> String {
+    let mut order = String::new();
+    let all_cycles = draws.iter().flat_map(|draw| cycles(&draw.pairs));
+    for (i, cycle) in all_cycles.enumerate() {
+        writeln!(
+            order,
+            "{}. First {} opens, then {}.",
+            i + 1,
+            cycle[0],
+            cycle[1..].join(", then ")
+        )
+        .unwrap();
+    }
+    order
+}
+
+/// Return the recipient's verification token: an HMAC of who they are.
+fn token(secret: &[u8], recipient: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC takes any key size");
+    mac.update(recipient.as_bytes());
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Return true if the token is the recipient's for this secret.
+pub fn verify_token(secret: &[u8], recipient: &str, token: &str) -> bool {
+    let bytes: Option<Vec<u8>> = (0..token.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(token.get(i..i + 2)?, 16).ok())
+        .collect();
+    let Some(bytes) = bytes else {
+        return false;
+    };
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC takes any key size");
+    mac.update(recipient.as_bytes());
+    mac.verify_slice(&bytes).is_ok()
+}
+
+/// Add each recipient's verification token to the end of their message.
+pub fn add_tokens(msgs: &mut [Message], secret: &[u8]) {
+    for msg in msgs {
+        let token = token(secret, &msg.email);
+        msg.body.push_str(&format!("Verification token: {token}\n"));
+    }
+}
+
+/// XOR the bytes with a keystream of SHA-256 hashes of the token, the same
+/// as the reveal page does to read them back.
+fn xor_keystream(token: &str, bytes: &mut [u8]) {
+    for (i, chunk) in bytes.chunks_mut(32).enumerate() {
+        let key = Sha256::digest(format!("key:{token}:{i}").as_bytes());
+        for (byte, k) in chunk.iter_mut().zip(key) {
+            *byte ^= k;
+        }
+    }
+}
+
+/// The reveal page; `BLOBS` is replaced by the encrypted messages.
+const REVEAL_PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Secret Santa</title>
+</head>
+<body>
+<h1>Secret Santa</h1>
+<p>Enter the token you were sent to see who you give to.</p>
+<input id="token" size="64"> <button onclick="reveal()">Reveal</button>
+<pre id="message"></pre>
+<script>
+const blobs = BLOBS;
+async function sha256(text) {
+  const digest = await crypto.subtle.digest("SHA-256", new TextEncoder().encode(text));
+  return new Uint8Array(digest);
+}
+async function reveal() {
+  const token = document.getElementById("token").value.trim();
+  const id = Array.from(await sha256("id:" + token), b => b.toString(16).padStart(2, "0")).join("");
+  const message = document.getElementById("message");
+  const blob = blobs[id];
+  if (!blob) {
+    message.textContent = "No assignment for that token.";
+    return;
+  }
+  const bytes = new Uint8Array(blob.match(/../g).map(h => parseInt(h, 16)));
+  for (let i = 0; i * 32 < bytes.length; i++) {
+    const key = await sha256("key:" + token + ":" + i);
+    for (let j = 0; j < 32 && i * 32 + j < bytes.length; j++) {
+      bytes[i * 32 + j] ^= key[j];
+    }
+  }
+  message.textContent = new TextDecoder().decode(bytes);
+}
+</script>
+</body>
+</html>
+"#;
+
+/// Return a page where each recipient enters their token to read their own
+/// message, so it can be hosted instead of emailing the messages.
+///
+/// Each message is XORed with a keystream made from the recipient's token
+/// and filed under a hash of the token, so the page holds no plaintext and a
+/// token opens only its own message. The tokens are the keys: anyone with
+/// one, or with the secret that makes them, can read that message, so an
+/// organizer who wants to stay blind shouldn't keep the secret. The page
+/// still shows how many messages there are and how long each is.
+pub fn reveal_page(msgs: &[Message], secret: &[u8]) -> String {
+    let blobs: BTreeMap<String, String> = msgs
+        .iter()
+        .map(|msg| {
+            let token = token(secret, &msg.email);
+            let mut bytes = msg.body.clone().into_bytes();
+            xor_keystream(&token, &mut bytes);
+            let blob = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+            (sha256_hex(format!("id:{token}").as_bytes()), blob)
+        })
+        .collect();
+    REVEAL_PAGE.replace("BLOBS", &serde_json::to_string(&blobs).unwrap())
+}
+
+/// Return the message sending the recipient only their token for the reveal
+/// page.
+pub fn token_message(msg: &Message, secret: &[u8], footer: &str) -> Message {
+    let token = token(secret, &msg.email);
+    Message {
+        body: format!(
+            "Enter this token on the Secret Santa page to see who you give to:\n{token}\n{footer}\n"
+        ),
+        ..msg.clone()
+    }
+}
+
+/// Read the token secret from its file.
+pub fn read_secret(path: &Path) -> Vec<u8> {
+    let secret = std::fs::read(path).expect("Failed opening token secret");
+    secret.trim_ascii().to_vec()
+}
+
+/// A drawn solution sealed by `commit` for `reveal`. The nonce keeps anyone
+/// from finding the solution by hashing every possible one.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Sealed {
+    pub nonce: String,
+    pub year: u16,
+    pub draws: Vec<Draw>,
+}
+
+/// ROT13 some text. It only keeps the organizer from reading the sealed
+/// solution by accident; it is not encryption.
+fn rot13(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            'a'..='m' | 'A'..='M' => (c as u8 + 13) as char,
+            'n'..='z' | 'N'..='Z' => (c as u8 - 13) as char,
+            _ => c,
+        })
+        .collect()
+}
+
+/// Return the sealed file's contents and the commitment to them.
+pub fn seal(sealed: &Sealed) -> (String, String) {
+    let plain = ron::ser::to_string_pretty(sealed, PrettyConfig::default()).unwrap();
+    let commitment = sha256_hex(plain.as_bytes());
+    (rot13(&plain), commitment)
+}
+
+/// Return the sealed solution if it matches the commitment.
+pub fn unseal(contents: &str, commitment: &str) -> Result<Sealed, String> {
+    let plain = rot13(contents);
+    if sha256_hex(plain.as_bytes()) != commitment {
+        return Err(String::from(
+            "Sealed solution does not match the commitment!",
+        ));
+    }
+    ron::de::from_str(&plain).map_err(|e| format!("Failed parsing sealed solution: {e}"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn p0() -> Person {
+        Person {
+            name: String::from("First Last"),
+            email: String::from("name@email.com"),
+            ..Default::default()
+        }
+    }
+
+    fn person(name: &str) -> Person {
+        Person {
+            name: String::from(name),
+            email: format!("{}@email.com", name.to_lowercase()),
+            ..Default::default()
+        }
+    }
+
+    fn input(names: &[&str]) -> Input {
+        Input {
+            people: names.iter().map(|n| person(n)).collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Draw with the default options, choosing with a fixed seed.
+    fn draw_default(input: &Input) -> Vec<Draw> {
+        draw_with(input, None, &mut RandomSelector(seeded_rng(Some(1)))).unwrap()
+    }
+
+    /// Draw with the default options, the prior, and the selector.
+    fn draw_with(
+        input: &Input,
+        prior: Option<&Solution>,
+        selector: &mut dyn Selector,
+    ) -> Result<Vec<Draw>, String> {
+        draw(
+            input,
+            &DrawOptions::default(),
+            prior,
+            selector,
+            &mut AuditEntry::default(),
+            &mut Timings::default(),
+        )
+    }
+
+    /// Chooses the first solution after checking every one it could choose
+    /// passes.
+    struct CheckEvery<F>(F);
+
+    impl<F: Fn(&[Pair<String>]) -> bool> Selector for CheckEvery<F> {
+        fn select(&mut self, solutions: &[Vec<Pair<String>>]) -> usize {
+            assert!(solutions.iter().all(|pairs| (self.0)(pairs)));
+            0
+        }
+    }
+
+    #[test]
+    fn balanced_households_beat_worst() {
+        let mut input = input(&["A", "B", "C", "D", "E", "F", "G", "H", "I"]);
+        input.blacklist_sets = vec![
+            vec!["A".into(), "B".into(), "C".into(), "D".into()],
+            vec!["E".into(), "F".into()],
+        ];
+        let households = input.households();
+        let mut solutions = solve(&input);
+        let worst = solutions
+            .iter()
+            .map(|pairs| household_balance_penalty(pairs, &households))
+            .fold(0.0, f64::max);
+        retain_best(&mut solutions, |pairs| {
+            household_balance_penalty(pairs, &households)
+        });
+        assert!(!solutions.is_empty());
+        for pairs in &solutions {
+            assert!(household_balance_penalty(pairs, &households) < worst);
+        }
+    }
+
+    #[test]
+    fn exec_from_input_file() {
+        let mut input = input(&["A", "B", "C"]);
+        assert!(delivery_commands(&[], &input).is_empty());
+        input.exec = Some("bin/mail-app".into());
+        assert_eq!(delivery_commands(&[], &input), ["bin/mail-app"]);
+        let flags = [String::from("mail"), String::from("backup-mail")];
+        assert_eq!(delivery_commands(&flags, &input), ["mail", "backup-mail"]);
+    }
+
+    #[test]
+    #[cfg(feature = "exec")]
+    fn exec_falls_back() {
+        let input = input(&["A", "B", "C"]);
+        let msgs = || vec![compose_reminder(&input.people[0], &input.footer())];
+        let delivery = |exec| Delivery {
+            exec,
+            contact_exec: &[],
+            smtp: None,
+            dry_run: false,
+            verbose: false,
+        };
+        assert!(delivery(vec!["false"]).deliver(msgs()).is_err());
+        assert!(delivery(vec!["false", "true"]).deliver(msgs()).is_ok());
+    }
+
+    #[test]
+    fn honor_rotates_by_year() {
+        let mut input = input(&["Grandma", "A", "B", "C"]);
+        input.honor = Some(HonorRotation {
+            giver: "Grandma".into(),
+            receivers: vec!["A".into(), "B".into(), "C".into()],
+        });
+        // 2025 % 3 == 0
+        let receivers: Vec<String> = (2025..2029)
+            .map(|year| input.honor_pair(year).unwrap().receiver)
+            .collect();
+        assert_eq!(receivers, ["A", "B", "C", "A"]);
+
+        input.for_year(2026).unwrap();
+        for pairs in solve(&input) {
+            assert!(pairs.contains(&Pair::new("Grandma".into(), "B".into())));
+        }
+    }
+
+    #[test]
+    fn honor_checks_households() {
+        let mut input = input(&["Grandma", "A", "B", "C"]);
+        input.blacklist_sets = vec![vec!["Grandma".into(), "A".into()]];
+        input.honor = Some(HonorRotation {
+            giver: "Grandma".into(),
+            receivers: vec!["A".into()],
+        });
+        assert_eq!(
+            input.clone().for_year(2025).unwrap_err().to_string(),
+            "Giver of honor 'Grandma' and 'A' are in the same household."
+        );
+        input.honor.as_mut().unwrap().receivers = vec!["Z".into()];
+        assert_eq!(
+            input.for_year(2025),
+            Err(InputError::UnknownHonor("Z".into()))
+        );
+    }
+
+    #[test]
+    fn audit_log_appends() {
+        let path = std::env::temp_dir().join(format!("audit-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut entry = AuditEntry {
+            input_hash: sha256_hex(b"input"),
+            people: 3,
+            solutions: 2,
+            chosen: vec![0],
+            seed: Some(7),
+            delivery: "exec",
+            outcome: String::from("ok"),
+            ..Default::default()
+        };
+        append_audit(&path, &entry).unwrap();
+        entry.chosen = vec![1];
+        append_audit(&path, &entry).unwrap();
+        let log = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_ne!(lines[0], lines[1]);
+        assert!(lines[1].contains("people=3 solutions=2 chosen=1 seed=7 delivery=exec outcome=ok"));
+    }
+
+    #[test]
+    fn group_label_in_messages() {
+        let mut input = input(&["A", "B", "C", "D", "E", "F"]);
+        input.groups = vec![
+            Group {
+                label: String::from("Cousins"),
+                members: vec!["A".into(), "B".into(), "C".into()],
+            },
+            Group {
+                label: String::from("Aunts"),
+                members: vec!["D".into(), "E".into(), "F".into()],
+            },
+        ];
+        for (label, draw) in input.draws() {
+            let label = label.unwrap();
+            let pairs = solve(&draw).swap_remove(0);
+            assert_eq!(pairs.len(), 3);
+            for pair in &pairs {
+                let draw = Draw {
+                    label: Some(label.clone()),
+                    ..Default::default()
+                };
+                let msg = compose_message(pair, &input, &draw).unwrap();
+                assert!(msg
+                    .body
+                    .contains(&format!("You're in the '{label}' exchange.")));
+            }
+        }
+    }
+
+    #[test]
+    fn member_of_two_groups() {
+        let mut input = input(&["A", "B", "C", "D", "E"]);
+        input.people[0].groups = vec!["Cousins".into(), "Aunts".into()];
+        input.groups = vec![
+            Group {
+                label: String::from("Cousins"),
+                members: vec!["B".into(), "C".into()],
+            },
+            Group {
+                label: String::from("Aunts"),
+                members: vec!["D".into(), "E".into()],
+            },
+        ];
+        assert!(input.validate().is_ok());
+        for (_, draw) in input.draws() {
+            let pairs = solve(&draw).swap_remove(0);
+            assert_eq!(pairs.len(), 3);
+            assert!(pairs.iter().any(|p| p.receiver == "A"));
+            assert!(pairs.iter().any(|p| p.giver == "A"));
+        }
+    }
+
+    #[test]
+    fn person_in_missing_group() {
+        let mut input = input(&["A", "B", "C"]);
+        input.people[0].groups = vec!["Aunts".into()];
+        assert_eq!(
+            input.validate().unwrap_err().to_string(),
+            "Group named 'Aunts' present in person 'A' but not found in groups."
+        );
+    }
+
+    #[test]
+    fn timings_cover_phases() {
+        let input = input(&["A", "B", "C", "D"]);
+        let mut timings = Timings::default();
+        let encoder = timings.time("encoding", || rules_encoder::<cadical::Solver>(&input));
+        let solutions = solve_from(&input, encoder, Variety::Exact, 100, &mut timings);
+        timings.time("selection", || std::thread::sleep(Duration::from_millis(5)));
+        assert!(!solutions.is_empty());
+        // It stops at the first solve finding nothing.
+        assert_eq!(timings.solves as usize, solutions.len() + 1);
+        let printed = timings.to_string();
+        let lines: Vec<&str> = printed.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("encoding: "));
+        assert!(lines[1].starts_with("solving: "));
+        assert!(lines[1].ends_with(&format!("per solve over {} solves)", timings.solves)));
+        assert!(lines[2].starts_with("selection: "));
+        assert!(lines[3].starts_with("total: "));
+        let phases: Duration = timings.phases.iter().map(|(_, d)| *d).sum();
+        assert!(phases >= Duration::from_millis(5));
+        assert!(phases <= timings.start.elapsed());
+    }
+
+    #[test]
+    fn same_surname_excluded() {
+        let mut input = input(&["Ann Lee", "Bo Lee", "Cy Park", "Di Park", "Ed"]);
+        input.rules.forbid_same_surname = true;
+        assert_eq!(
+            input.surname_sets(),
+            vec![vec!["Ann Lee", "Bo Lee"], vec!["Cy Park", "Di Park"]]
+        );
+        let feasible = feasible_receivers(&input);
+        assert_eq!(feasible["Ann Lee"], vec!["Cy Park", "Di Park", "Ed"]);
+        assert_eq!(
+            feasible["Ed"],
+            vec!["Ann Lee", "Bo Lee", "Cy Park", "Di Park"]
+        );
+    }
+
+    #[test]
+    fn same_country_only() {
+        let mut input = input(&["A", "B", "C", "D", "E", "F", "G"]);
+        for (person, country) in input
+            .people
+            .iter_mut()
+            .zip(["US", "US", "US", "DE", "DE", "DE"])
+        {
+            person.country = Some(String::from(country));
+        }
+        input.rules.same_country_only = true;
+        let feasible = feasible_receivers(&input);
+        assert_eq!(feasible["A"], vec!["B", "C", "G"]);
+        assert_eq!(feasible["D"], vec!["E", "F", "G"]);
+        assert_eq!(feasible["G"], vec!["A", "B", "C", "D", "E", "F"]);
+
+        input.people[6].country = Some(String::from("FR"));
+        assert_eq!(infeasible_layer(&input), Some("the same country rule"));
+    }
+
+    #[test]
+    fn empty_fields_not_written() {
+        let input = input(&["A", "B", "C"]);
+        let contents = ron::ser::to_string_pretty(&input, PrettyConfig::default()).unwrap();
+        for field in [
+            "exec",
+            "honor",
+            "encouraged",
+            "groups",
+            "profiles",
+            "history_message_limit",
+            "different_cycle_sets",
+            "allow_mutual",
+            "exact_cycles",
+            "forbid_reversal",
+        ] {
+            assert!(!contents.contains(field), "{field} written");
+        }
+        let read: Input = ron::de::from_str(&contents).unwrap();
+        assert_eq!(read.names(), input.names());
+    }
+
+    #[test]
+    fn add_person_to_household() {
+        let mut input = input(&["A", "B", "C", "D"]);
+        input.blacklist_sets = vec![vec!["A".into(), "B".into()]];
+        input.add_person(person("E"), Some("B")).unwrap();
+        input.add_person(person("F"), Some("C")).unwrap();
+        assert_eq!(
+            input.add_person(person("E"), None),
+            Err(String::from("Person named 'E' already in people set."))
+        );
+        assert!(input.add_person(person("G"), Some("Z")).is_err());
+        let contents = ron::ser::to_string_pretty(&input, PrettyConfig::default()).unwrap();
+        let input: Input = ron::de::from_str(&contents).unwrap();
+        assert_eq!(input.names(), vec!["A", "B", "C", "D", "E", "F"]);
+        assert_eq!(
+            input.blacklist_sets,
+            vec![vec!["A", "B", "E"], vec!["C", "F"]]
+        );
+    }
+
+    #[test]
+    fn couples_share_households() {
+        let mut input = input(&["A", "B", "C", "D", "E", "F"]);
+        input.blacklist_sets = vec![vec!["A".into(), "B".into()]];
+        input.couples = vec![("B".into(), "C".into()), ("E".into(), "F".into())];
+        assert!(input.validate().is_ok());
+        assert_eq!(
+            input.couple_households(),
+            vec![vec!["A", "B", "C"], vec!["E", "F"]]
+        );
+        assert_eq!(input.households(), vec![vec!["A", "B"]]);
+        let feasible = feasible_receivers(&input);
+        assert_eq!(feasible["A"], vec!["D", "E", "F"]);
+        assert_eq!(feasible["E"], vec!["A", "B", "C"]);
+
+        input.couples.push(("D".into(), "Z".into()));
+        assert!(input.validate().is_err());
+    }
+
+    #[test]
+    fn named_households_merge() {
+        let mut input = input(&["A", "B", "C", "D", "E", "F"]);
+        input.blacklist_sets = vec![vec!["A".into(), "B".into()]];
+        for (i, household) in [(1, "Smith"), (2, "Smith"), (3, "Jones"), (4, "Jones")] {
+            input.people[i].household = Some(String::from(household));
+        }
+        assert_eq!(
+            input.households(),
+            vec![vec!["A", "B", "C"], vec!["D", "E"]]
+        );
+        let feasible = feasible_receivers(&input);
+        assert_eq!(feasible["A"], vec!["D", "E", "F"]);
+        assert_eq!(feasible["D"], vec!["A", "B", "C"]);
+
+        input.add_person(person("G"), Some("E")).unwrap();
+        assert_eq!(input.people[6].household.as_deref(), Some("Jones"));
+    }
+
+    #[test]
+    fn novel_beat_worst() {
+        let mut input = input(&["A", "B", "C", "D", "E"]);
+        let mut solutions = solve_variety(&input, Variety::Variations);
+        for (year, pairs) in (2020..).zip(solutions.iter().take(3)) {
+            input.history.push(Solution {
+                year,
+                pairs: pairs.clone(),
+                ..Default::default()
+            });
+        }
+        let worst = solutions
+            .iter()
+            .map(|pairs| novelty_score(pairs, &input.history))
+            .min()
+            .unwrap();
+        assert_eq!(worst, 0);
+        retain_best(&mut solutions, |pairs| {
+            -(novelty_score(pairs, &input.history) as f64)
+        });
+        assert!(novelty_score(&solutions[0], &input.history) > worst);
+    }
+
+    #[test]
+    fn history_unknown_name() {
+        let mut input = input(&["A", "B", "C"]);
+        assert_eq!(input.check_history(), Ok(()));
+        input.history.push(Solution {
+            year: 2024,
+            pairs: vec![Pair::new("A".into(), "Z".into())],
+            ..Default::default()
+        });
+        let error = input.check_history().unwrap_err();
+        assert_eq!(error, InputError::UnknownReceiver(String::from("Z")));
+        assert_eq!(
+            error.to_string(),
+            "Receiver named 'Z' present in history but not found in people set."
+        );
+    }
+
+    #[test]
+    fn excluded_givers() {
+        let mut input = input(&["A", "B", "C", "D", "E"]);
+        input.people[0].exclude_givers = vec!["B".into(), "C".into()];
+        assert_eq!(input.check_exclude_givers(), Ok(()));
+        let feasible = feasible_receivers(&input);
+        assert!(!feasible["B"].contains(&String::from("A")));
+        assert!(!feasible["C"].contains(&String::from("A")));
+        assert!(feasible["D"].contains(&String::from("A")));
+        assert!(feasible["A"].contains(&String::from("B")));
+
+        input.people[1].exclude_givers = vec!["Z".into()];
+        assert_eq!(
+            input.check_exclude_givers(),
+            Err(InputError::MissingExcludedGiver {
+                person: String::from("B"),
+                giver: String::from("Z"),
+            })
+        );
+    }
+
+    #[test]
+    fn whitelist_conflicts() {
+        let mut input = input(&["A", "B", "C"]);
+        let pair = Pair::new(String::from("A"), String::from("B"));
+        input.whitelist.push(pair.clone());
+        assert!(input.validate().is_ok());
+        input.blacklist.push(pair);
+        let error = input.validate().unwrap_err();
+        assert_eq!(
+            error,
+            InputError::WhitelistBlacklisted {
+                giver: String::from("A"),
+                receiver: String::from("B"),
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            "Pair 'A' to 'B' present in both whitelist and blacklist."
+        );
+
+        input.blacklist.clear();
+        input.blacklist_sets.push(vec!["B".into(), "A".into()]);
+        assert_eq!(
+            input.validate(),
+            Err(InputError::WhitelistHousehold {
+                giver: String::from("A"),
+                receiver: String::from("B"),
+            })
+        );
+    }
+
+    #[test]
+    fn duplicate_names() {
+        let mut input = input(&["John", "Sean", "Shane"]);
+        assert!(input.validate().is_ok());
+        input.people.push(person("John"));
+        assert_eq!(
+            input.validate(),
+            Err(InputError::DuplicateName(String::from("John")))
+        );
+    }
+
+    #[test]
+    fn invalid_emails() {
+        let mut input = input(&["A", "B"]);
+        assert!(input.validate().is_ok());
+        input.people[1].email = String::from("b.email.com");
+        assert_eq!(
+            input.validate(),
+            Err(InputError::InvalidEmail {
+                person: "B".into(),
+                email: "b.email.com".into()
+            })
+        );
+        input.people[1].email = String::new();
+        assert_eq!(
+            input.validate().unwrap_err().to_string(),
+            "Email '' of person named 'B' is not a valid address."
+        );
+        for bad in [
+            "b@",
+            "@email.com",
+            "b@email",
+            "b@email.",
+            "b@.com",
+            "b c@email.com",
+        ] {
+            assert!(!is_email(bad), "{bad}");
+        }
+        assert!(is_email("first.last+santa@mail.example.com"));
+    }
+
+    #[test]
+    fn json_input() {
+        let json = r#"{
+            "people": [
+                {"name": "John", "email": "john@email.com"},
+                {"name": "Sean", "email": "sean@email.com", "household": "B"}
+            ],
+            "whitelist": [],
+            "blacklist": [{"giver": "John", "receiver": "Sean"}],
+            "blacklist_sets": [],
+            "history": [],
+            "exec": null,
+            "honor": null,
+            "history_message_limit": null
+        }"#;
+        let path = Path::new("in.json");
+        let input = parse_input(path, json.as_bytes()).unwrap();
+        assert_eq!(input.names(), vec!["John", "Sean"]);
+        assert_eq!(input.people[1].household.as_deref(), Some("B"));
+        assert_eq!(
+            input.blacklist,
+            vec![Pair::new("John".into(), "Sean".into())]
+        );
+        let again = parse_input(path, serialize_input(path, &input).as_bytes()).unwrap();
+        assert_eq!(again.people, input.people);
+    }
+
+    #[test]
+    fn skeleton_from_list() {
+        let input =
+            skeleton_input("name,email\nAnn Lee, ann@email.com\n\nBo,bo@email.com\n").unwrap();
+        assert_eq!(input.names(), vec!["Ann Lee", "Bo"]);
+        assert_eq!(input.people[0].email, "ann@email.com");
+        assert!(input.whitelist.is_empty() && input.history.is_empty());
+        let path = Path::new("in.ron");
+        let again = parse_input(path, serialize_input(path, &input).as_bytes()).unwrap();
+        assert_eq!(again.people, input.people);
+
+        assert_eq!(
+            skeleton_input("Ann,ann@email.com\nBo").unwrap_err(),
+            "Line 2 has no 'name,email': 'Bo'."
+        );
+        assert_eq!(
+            skeleton_input("Ann,ann@email.com\nAnn,ann@email.com").unwrap_err(),
+            "Person named 'Ann' present more than once in people set."
+        );
+    }
+
+    #[test]
+    fn parse_error_reported() {
+        let error = parse_input(Path::new("in.ron"), b"(people: [").unwrap_err();
+        assert!(error.to_string().starts_with("Failed parsing 'in.ron': "));
+        assert!(parse_input(Path::new("in.json"), b"{").is_err());
+
+        let missing = std::env::temp_dir().join(format!("missing-{}.ron", std::process::id()));
+        let error = read_input(&missing, 2024, None, None, &mut Timings::default()).unwrap_err();
+        assert!(matches!(error, InputError::Read { .. }));
+    }
+
+    #[test]
+    fn stdin_input_format() {
+        let stdin = Path::new("-");
+        let json = r#" {"people": [{"name": "A", "email": "a@email.com"}],
+            "whitelist": [], "blacklist": [], "blacklist_sets": [], "history": [],
+            "exec": null, "honor": null, "history_message_limit": null}"#;
+        assert_eq!(
+            parse_input(stdin, json.as_bytes()).unwrap().names(),
+            vec!["A"]
+        );
+        let ron = serialize_input(Path::new("in.ron"), &input(&["A", "B"]));
+        assert_eq!(
+            parse_input(stdin, ron.as_bytes()).unwrap().names(),
+            vec!["A", "B"]
+        );
+    }
+
+    #[test]
+    fn custom_branding() {
+        let mut input = input(&["A", "B", "C"]);
+        let pair = Pair::new(String::from("A"), String::from("B"));
+        let msg = compose_message(&pair, &input, &Draw::default()).unwrap();
+        assert!(msg.body.ends_with(
+            "Brought to you by secret-santa[1].\n\n[1]: https://github.com/shanecelis/secret-santa\n\n"
+        ));
+        input.branding = Some(Branding {
+            name: String::from("Acme Gifts"),
+            url: String::from("https://gifts.acme.com"),
+        });
+        let msg = compose_message(&pair, &input, &Draw::default()).unwrap();
+        assert!(msg
+            .body
+            .contains("Brought to you by Acme Gifts[1].\n\n[1]: https://gifts.acme.com\n"));
+        assert!(!msg.body.contains("shanecelis"));
+    }
+
+    #[test]
+    fn save_history_appends() {
+        let path = std::env::temp_dir().join(format!("history-{}.ron", std::process::id()));
+        let draw = |giver: &str| Draw {
+            pairs: vec![Pair::new(giver.into(), "B".into())],
+            ..Default::default()
+        };
+        save_history(&path, 2023, &[draw("A")]).unwrap();
+        save_history(&path, 2024, &[draw("C"), draw("D")]).unwrap();
+        save_history(&path, 2024, &[draw("A"), draw("D")]).unwrap();
+        let history: Vec<Solution> =
+            ron::de::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].year, 2023);
+        assert_eq!(history[1].year, 2024);
+        assert!(history[1].exclude_pairs);
+        assert_eq!(
+            history[1].pairs,
+            vec![
+                Pair::new("A".into(), "B".into()),
+                Pair::new("D".into(), "B".into())
+            ]
+        );
+    }
+
+    #[test]
+    fn checklist_has_event() {
+        let mut input = input(&["A", "B", "C"]);
+        input.budget = Some(String::from("$25"));
+        input.event_date = Some(String::from("December 20th"));
+        let checklist = checklist(&input);
+        assert!(checklist.contains("- Participants: 3\n"));
+        assert!(checklist.contains("- Budget: $25\n"));
+        assert!(checklist.contains("- Date: December 20th\n"));
+        assert!(!checklist.contains("Location"));
+        assert!(checklist.ends_with("- [ ] A\n- [ ] B\n- [ ] C\n"));
+    }
+
+    #[test]
+    fn nearby_beat_worst() {
+        let mut input = input(&["A", "B", "C", "D", "E", "F"]);
+        input.distances = vec![
+            ("A".into(), "B".into(), 10),
+            ("B".into(), "C".into(), 10),
+            ("C".into(), "A".into(), 10),
+            ("D".into(), "E".into(), 10),
+            ("E".into(), "F".into(), 10),
+            ("F".into(), "D".into(), 10),
+        ];
+        input.missing_distance = Some(1000);
+        let mut solutions = solve_variety(&input, Variety::Variations);
+        let worst = solutions
+            .iter()
+            .map(|pairs| input.total_distance(pairs))
+            .max()
+            .unwrap();
+        retain_best(&mut solutions, |pairs| input.total_distance(pairs) as f64);
+        let best = input.total_distance(&solutions[0]);
+        assert!(best < worst);
+    }
+
+    struct MockProbe;
+
+    impl Probe for MockProbe {
+        fn probe(&mut self, address: &str) -> ProbeResult {
+            if address == "b@email.com" {
+                ProbeResult::Rejected(String::from("550 No such user"))
+            } else {
+                ProbeResult::Accepted
+            }
+        }
+    }
+
+    #[test]
+    fn check_emails_reports_rejected() {
+        let input = input(&["A", "B", "C"]);
+        let bad = check_emails(&input.people, &mut MockProbe);
+        assert_eq!(bad.len(), 1);
+        assert_eq!(bad[0].0.name, "B");
+        assert_eq!(
+            bad[0].1,
+            ProbeResult::Rejected(String::from("550 No such user"))
+        );
+    }
+
+    #[test]
+    fn route_by_contact_method() {
+        let mut input = input(&["A", "B", "C"]);
+        input.people[1].contact = Some(Contact {
+            method: String::from("sms"),
+            address: String::from("555-0100"),
+        });
+        input.people[2].contact = Some(Contact {
+            method: String::from("pigeon"),
+            address: String::from("the roof"),
+        });
+        let contact_exec = vec![(String::from("sms"), String::from("bin/sms"))];
+        let delivery = Delivery {
+            exec: vec!["bin/mail-app"],
+            contact_exec: &contact_exec,
+            smtp: None,
+            dry_run: true,
+            verbose: false,
+        };
+        let routes: Vec<_> = ["A", "B", "C"]
+            .iter()
+            .map(|name| {
+                let pair = Pair::new(name.to_string(), String::from("A"));
+                let msg = compose_message(&pair, &input, &Draw::default()).unwrap();
+                delivery
+                    .route(&msg)
+                    .map(|(command, address)| (command.to_string(), address.to_string()))
+                    .unwrap()
+            })
+            .collect();
+        assert_eq!(
+            routes,
+            [
+                ("bin/mail-app".into(), "A <a@email.com>".into()),
+                ("bin/sms".into(), "555-0100".into()),
+                ("bin/mail-app".into(), "C <c@email.com>".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn combine_shared_emails() {
+        let mut input = input(&["A", "B", "C"]);
+        input.people[1].email = String::from("A@email.com");
+        let delivery = Delivery {
+            exec: vec!["bin/mail-app"],
+            contact_exec: &[],
+            smtp: None,
+            dry_run: true,
+            verbose: false,
+        };
+        let draw = Draw {
+            pairs: vec![
+                Pair::new("A".into(), "B".into()),
+                Pair::new("B".into(), "C".into()),
+                Pair::new("C".into(), "A".into()),
+            ],
+            ..Default::default()
+        };
+        let msgs = delivery.combine(compose_messages(&[draw], &input));
+        assert_eq!(msgs.len(), 2);
+        assert!(msgs[0].body.contains("for B."));
+        assert!(msgs[0].body.contains("for C."));
+        assert!(!msgs[1].body.contains("for B."));
+    }
+
+    #[test]
+    fn stable_from_adding_one_person() {
+        let prior = vec![
+            Pair::new("A".into(), "B".into()),
+            Pair::new("B".into(), "C".into()),
+            Pair::new("C".into(), "D".into()),
+            Pair::new("D".into(), "A".into()),
+        ];
+        let input = input(&["A", "B", "C", "D", "E"]);
+        let mut solutions = solve(&input);
+        solutions.extend(solve_near(&input, &prior));
+        retain_best(&mut solutions, |pairs| -(overlap(pairs, &prior) as f64));
+        for pairs in &solutions {
+            assert_eq!(pairs.len(), 5);
+            // Fitting in E breaks exactly one of the prior pairs.
+            assert_eq!(overlap(pairs, &prior), 3);
+        }
+    }
+
+    #[test]
+    fn prior_keeps_exact_cycles() {
+        let mut input = input(&["A", "B", "C", "D", "E", "F"]);
+        let pair = |g: &str, r: &str| Pair::new(g.to_string(), r.to_string());
+        let prior = Solution {
+            pairs: vec![
+                pair("A", "B"),
+                pair("B", "C"),
+                pair("C", "A"),
+                pair("D", "E"),
+                pair("E", "F"),
+                pair("F", "D"),
+            ],
+            ..Default::default()
+        };
+        input.rules.exact_cycles = Some(1);
+        let mut one_cycle = CheckEvery(|pairs: &[Pair<String>]| cycles(pairs).len() == 1);
+        draw_with(&input, Some(&prior), &mut one_cycle).unwrap();
+    }
+
+    #[test]
+    fn what_if_blacklist_diff() {
+        let mut input = input(&["A", "B", "C", "D", "E", "F"]);
+        let mut prior = solve(&input).swap_remove(0);
+        prior.sort_by(|a, b| a.giver.cmp(&b.giver));
+        let broken = prior[0].clone();
+        input.blacklist.push(broken.clone());
+        let prior = Solution {
+            pairs: prior,
+            ..Default::default()
+        };
+        let draws = draw_with(
+            &input,
+            Some(&prior),
+            &mut RandomSelector(seeded_rng(Some(1))),
+        )
+        .unwrap();
+        let diff = solution_diff(&prior.pairs, &draws[0].pairs);
+        assert!(diff.contains(&format!("Changed: {} gives to ", broken.giver)));
+        assert!(diff.contains(&format!(" instead of {}\n", broken.receiver)));
+        assert!(!diff.contains("Added"));
+        assert!(!diff.contains("Removed"));
+        let changed = diff.matches("Changed").count();
+        assert!(diff.ends_with(&format!("Unchanged: {} pairs\n", 6 - changed)));
+
+        let mut fewer = draws[0].pairs.clone();
+        let removed = fewer.pop().unwrap();
+        let diff = solution_diff(&draws[0].pairs, &fewer);
+        assert!(diff.contains(&format!(
+            "Removed: {} gave to {}",
+            removed.giver, removed.receiver
+        )));
+    }
+
+    #[test]
+    fn cycles_of_solution() {
+        let pairs = vec![
+            Pair::new("D".into(), "E".into()),
+            Pair::new("A".into(), "C".into()),
+            Pair::new("E".into(), "D".into()),
+            Pair::new("C".into(), "B".into()),
+            Pair::new("B".into(), "A".into()),
+        ];
+        assert_eq!(cycles(&pairs), vec![vec!["A", "C", "B"], vec!["D", "E"]]);
+    }
+
+    #[test]
+    fn balance_attribute_in_cycles() {
+        let mut input = input(&["A", "B", "C", "D", "E", "F"]);
+        for (i, person) in input.people.iter_mut().enumerate() {
+            let dept = if i < 3 { "sales" } else { "dev" };
+            person.attributes.insert("dept".into(), dept.into());
+        }
+        let unbalanced = vec![
+            Pair::new("A".into(), "B".into()),
+            Pair::new("B".into(), "C".into()),
+            Pair::new("C".into(), "A".into()),
+            Pair::new("D".into(), "E".into()),
+            Pair::new("E".into(), "F".into()),
+            Pair::new("F".into(), "D".into()),
+        ];
+        assert!(!cycles_balanced(&unbalanced, &input.people, "dept"));
+        let mut solutions = solve(&input);
+        solutions.retain(|pairs| cycles_balanced(pairs, &input.people, "dept"));
+        assert!(!solutions.is_empty());
+        for pairs in &solutions {
+            for cycle in cycles(pairs) {
+                assert!(cycle.iter().any(|n| ["A", "B", "C"].contains(&n.as_str())));
+                assert!(cycle.iter().any(|n| ["D", "E", "F"].contains(&n.as_str())));
+            }
+        }
+    }
+
+    #[test]
+    fn history_duplicate_giver() {
+        let solution = Solution {
+            year: 2023,
+            exclude_pairs: true,
+            pairs: vec![
+                Pair::new("A".into(), "B".into()),
+                Pair::new("A".into(), "C".into()),
+                Pair::new("C".into(), "A".into()),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(
+            solution.permutation_problems(1),
+            ["'A' gives more than once"]
+        );
+    }
+
+    #[test]
+    fn forced_unique_solution() {
+        let mut input = input(&["A", "B", "C"]);
+        let solutions = solve(&input);
+        assert!(!is_unique(&input, &solutions[0]));
+
+        input.blacklist.push(Pair::new("A".into(), "C".into()));
+        let solutions = solve(&input);
+        assert_eq!(solutions.len(), 1);
+        assert!(is_unique(&input, &solutions[0]));
+    }
+
+    #[test]
+    fn check_provided_solution() {
+        let mut input = input(&["A", "B", "C", "D"]);
+        input.blacklist.push(Pair::new("A".into(), "B".into()));
+        let solution = |pairs: &[(&str, &str)]| Solution {
+            pairs: pairs
+                .iter()
+                .map(|(giver, receiver)| Pair::new(giver.to_string(), receiver.to_string()))
+                .collect(),
+            ..Default::default()
+        };
+        let valid = solution(&[("A", "C"), ("C", "B"), ("B", "D"), ("D", "A")]);
+        assert_eq!(check_solution(&input, &valid), Ok(()));
+        let invalid = solution(&[("A", "B"), ("B", "C"), ("C", "D"), ("D", "A")]);
+        assert_eq!(
+            check_solution(&input, &invalid),
+            Err(String::from(
+                "'A' giving to 'B' is inconsistent with the constraints."
+            ))
+        );
+        let partial = solution(&[("A", "C"), ("C", "B"), ("B", "D")]);
+        assert_eq!(
+            check_solution(&input, &partial),
+            Err(String::from("'D' gives to no one."))
+        );
+    }
+
+    #[test]
+    fn remind_only_listed() {
+        let input = input(&["A", "B", "C", "D"]);
+        let msgs = compose_reminders(&["B".into(), "Z".into(), "D".into()], &input);
+        let emails: Vec<&str> = msgs.iter().map(|m| m.email.as_str()).collect();
+        assert_eq!(emails, ["B <b@email.com>", "D <d@email.com>"]);
+        assert!(msgs[0]
+            .body
+            .contains("B, we haven't heard back from you yet."));
+    }
+
+    #[test]
+    fn reveal_matches_commitment() {
+        let input = input(&["A", "B", "C", "D"]);
+        let sealed = Sealed {
+            nonce: String::from("00ff"),
+            year: 2025,
+            draws: vec![Draw {
+                label: None,
+                pairs: solve(&input).swap_remove(0),
+                ..Default::default()
+            }],
+        };
+        let (contents, commitment) = seal(&sealed);
+        assert!(!contents.contains("giver"));
+        assert_eq!(unseal(&contents, &commitment), Ok(sealed));
+
+        let tampered = contents.replacen("N", "O", 1);
+        assert!(unseal(&tampered, &commitment).is_err());
+    }
+
+    #[test]
+    fn gift_back_loops() {
+        let history = vec![
+            Solution {
+                year: 2023,
+                exclude_pairs: false,
+                pairs: vec![Pair::new("B".into(), "C".into())],
+                ..Default::default()
+            },
+            Solution {
+                year: 2024,
+                exclude_pairs: false,
+                pairs: vec![Pair::new("C".into(), "A".into())],
+                ..Default::default()
+            },
+        ];
+        let rule = GiftBack {
+            max_length: 3,
+            lookback: 2,
+        };
+        let pairs = gift_back_pairs(&history, rule);
+        // A→C gives back to C, C→B to B, and A→B closes A→B→C→A.
+        assert_eq!(
+            pairs,
+            [
+                Pair::new("A".into(), "B".into()),
+                Pair::new("C".into(), "B".into()),
+                Pair::new("A".into(), "C".into()),
+            ]
+        );
+
+        let rule = GiftBack {
+            max_length: 3,
+            lookback: 1,
+        };
+        assert_eq!(
+            gift_back_pairs(&history, rule),
+            [Pair::new("A".into(), "C".into())]
+        );
+    }
+
+    #[test]
+    fn allow_mutual() {
+        let mut input = input(&["A", "B"]);
+        assert!(solve(&input).is_empty());
+        input.allow_mutual = true;
+        let solutions = solve(&input);
+        assert_eq!(solutions.len(), 1);
+        assert!(solutions[0].contains(&Pair::new("A".into(), "B".into())));
+        assert!(solutions[0].contains(&Pair::new("B".into(), "A".into())));
+    }
+
+    #[test]
+    fn two_gifts_per_person() {
+        let mut input = input(&["A", "B", "C", "D", "E"]);
+        input.gifts_per_person = Some(2);
+        let solutions = solve(&input);
+        assert!(!solutions.is_empty());
+        for pairs in &solutions {
+            assert_eq!(pairs.len(), 10);
+            assert_eq!(verify_solution(&input.names(), 2, pairs), Ok(()));
+            for pair in pairs {
+                let back = Pair::new(pair.receiver.clone(), pair.giver.clone());
+                assert!(!pairs.contains(&back));
+            }
+        }
+        assert_eq!(
+            verify_solution(&input.names(), 1, &solutions[0]),
+            Err(String::from("'A' gives 2 times instead of once!"))
+        );
+
+        input.rules.exact_cycles = Some(1);
+        assert_eq!(
+            input.validate().unwrap_err().to_string(),
+            "Rule 'exact_cycles' needs gifts per person to be 1, not 2."
+        );
+        input.gifts_per_person = Some(0);
+        assert_eq!(input.validate(), Err(InputError::NoGifts));
+    }
+
+    #[test]
+    fn infeasible_layer_found() {
+        let pair = |g: &str, r: &str| Pair::new(g.to_string(), r.to_string());
+        assert_eq!(
+            infeasible_layer(&input(&["A", "B"])),
+            Some("the core rules")
+        );
+
+        let mut input = input(&["A", "B", "C", "D"]);
+        assert_eq!(infeasible_layer(&input), None);
+        input.history.push(Solution {
+            year: 2024,
+            exclude_pairs: true,
+            pairs: vec![pair("A", "B"), pair("A", "C")],
+            ..Default::default()
+        });
+        input.whitelist.push(pair("A", "B"));
+        assert_eq!(infeasible_layer(&input), Some("the history"));
+        input.whitelist.push(pair("A", "C"));
+        assert_eq!(infeasible_layer(&input), Some("the whitelist"));
+        input.people[0].exclude_givers = vec!["B".into(), "C".into(), "D".into()];
+        assert_eq!(
+            infeasible_layer(&input),
+            Some("the blacklist and households")
+        );
+        let error = draw_with(&input, None, &mut RandomSelector(seeded_rng(Some(1)))).unwrap_err();
+        assert_eq!(
+            error,
+            "No secret santa solutions found!\n\
+             It becomes unsolvable with the blacklist and households; try relaxing those."
+        );
+    }
+
+    #[test]
+    fn most_preference_points() {
+        let mut input = input(&["A", "B", "C", "D", "E", "F"]);
+        let pair = |g: &str, r: &str| Pair::new(g.to_string(), r.to_string());
+        input.preferences = vec![
+            (pair("A", "B"), 2),
+            (pair("B", "C"), 2),
+            (pair("C", "A"), 2),
+            (pair("D", "E"), 1),
+            (pair("E", "F"), 1),
+            (pair("F", "D"), 1),
+            // Can't be had with A→B.
+            (pair("A", "C"), 1),
+            (pair("A", "D"), -5),
+        ];
+        assert_eq!(input.possible_points(), 15);
+        let best = solve_preferred(&input).unwrap();
+        assert_eq!(input.preference_points(&best), 14);
+        let draws = draw_default(&input);
+        assert_eq!(input.preference_points(&draws[0].pairs), 14);
+
+        // Preferences the hard constraints forbid are given up.
+        input.blacklist.push(pair("A", "B"));
+        let best = solve_preferred(&input).unwrap();
+        assert_eq!(input.preference_points(&best), 11);
+    }
+
+    #[test]
+    fn preferences_keep_exact_cycles() {
+        let mut input = input(&["A", "B", "C", "D", "E", "F"]);
+        let pair = |g: &str, r: &str| Pair::new(g.to_string(), r.to_string());
+        // Best as a cycle of its own.
+        input.preferences = vec![
+            (pair("A", "B"), 1),
+            (pair("B", "C"), 1),
+            (pair("C", "A"), 1),
+        ];
+        input.rules.exact_cycles = Some(1);
+        let mut one_cycle = CheckEvery(|pairs: &[Pair<String>]| cycles(pairs).len() == 1);
+        draw_with(&input, None, &mut one_cycle).unwrap();
+    }
+
+    #[test]
+    fn draw_reports_preference_points() {
+        let mut input = input(&["A", "B", "C", "D"]);
+        input.preferences = vec![(Pair::new("A".into(), "B".into()), 1)];
+        let draws = draw_default(&input);
+        assert_eq!(
+            draws[0].report.last().unwrap(),
+            "The chosen solution earns 1 of 1 preference points."
+        );
+    }
+
+    #[test]
+    fn encouraged_pairs_come_first() {
+        let mut input = input(&["A", "B", "C", "D", "E"]);
+        let solutions = solve(&input);
+        // The second solution shares no pairs with the first.
+        input.encouraged = solutions[1].clone();
+        let satisfied = |pairs: &[Pair<String>]| overlap(pairs, &input.encouraged);
+        assert_eq!(satisfied(&solutions[0]), 0);
+        let encouraged = solve(&input);
+        assert_eq!(satisfied(&encouraged[0]), 5);
+    }
+
+    #[test]
+    fn dot_has_edge_per_pair() {
+        let input = input(&["A", "B", "C", "D", "E"]);
+        let draws = vec![Draw {
+            label: None,
+            pairs: solve(&input).swap_remove(0),
+            ..Default::default()
+        }];
+        let dot = to_dot(&draws);
+        assert!(dot.starts_with("digraph secret_santa {\n"));
+        assert!(dot.ends_with("}\n"));
+        assert_eq!(dot.matches(" -> ").count(), 5);
+        for pair in &draws[0].pairs {
+            assert!(dot.contains(&format!("\"{}\" -> \"{}\"", pair.giver, pair.receiver)));
+        }
+    }
+
+    #[test]
+    fn reveal_order_traverses_cycles() {
+        let input = input(&["A", "B", "C", "D", "E", "F", "G"]);
+        let draws = vec![Draw {
+            pairs: solve(&input).swap_remove(0),
+            ..Default::default()
+        }];
+        let order = reveal_order(&draws);
+        let mut seen = 0;
+        for line in order.lines() {
+            let (_, names) = line.split_once(". First ").unwrap();
+            let names: Vec<&str> = names
+                .trim_end_matches('.')
+                .split(" opens, then ")
+                .flat_map(|rest| rest.split(", then "))
+                .collect();
+            for (i, giver) in names.iter().enumerate() {
+                let receiver = names[(i + 1) % names.len()];
+                assert!(draws[0]
+                    .pairs
+                    .contains(&Pair::new(giver.to_string(), receiver.to_string())));
+            }
+            seen += names.len();
+        }
+        assert_eq!(seen, 7);
+    }
+
+    #[test]
+    fn fallback_differs_from_primary() {
+        let mut input = input(&["A", "B", "C", "D", "E"]);
+        input.blacklist.push(Pair::new("A".into(), "C".into()));
+        let pairs = solve(&input).swap_remove(0);
+        let fallbacks = fallbacks(&input, &pairs);
+        assert_eq!(fallbacks.len(), pairs.len());
+        for (pair, fallback) in pairs.iter().zip(&fallbacks) {
+            assert_eq!(pair.giver, fallback.giver);
+            assert_ne!(pair.receiver, fallback.receiver);
+            assert_ne!(fallback.giver, fallback.receiver);
+            assert!(!input.blacklist.contains(fallback));
+        }
+    }
+
+    #[test]
+    fn list_words_by_lang() {
+        let names = ["A", "B", "C"];
+        assert_eq!(ListWords::for_lang(None).join(&names), "A, B, and C");
+        assert_eq!(ListWords::for_lang(Some("en")).join(&names[..1]), "A");
+        assert_eq!(ListWords::for_lang(Some("de")).join(&names), "A, B und C");
+        assert_eq!(
+            ListWords::for_lang(Some("es-MX")).join(&names[..2]),
+            "A y B"
+        );
+    }
+
+    #[test]
+    fn blacklist_until_year() {
+        let mut input = input(&["A", "B", "C"]);
+        input.blacklist = ron::de::from_str(
+            r#"[
+                (giver: "A", receiver: "B", until_year: Some(2023)),
+                (giver: "B", receiver: "C", until_year: Some(2024)),
+                (giver: "C", receiver: "A"),
+            ]"#,
+        )
+        .unwrap();
+        input.for_year(2024).unwrap();
+        assert_eq!(
+            input.blacklist,
+            [
+                Pair::new("B".into(), "C".into()),
+                Pair::new("C".into(), "A".into())
+            ]
+        );
+    }
+
+    #[test]
+    fn charity_receives_once() {
+        let mut input = input(&["A", "B", "C", "Charity"]);
+        input.people[3].charity = true;
+        input
+            .blacklist_sets
+            .push(vec!["A".into(), "Charity".into()]);
+        input.history.push(Solution {
+            year: 2023,
+            exclude_pairs: true,
+            pairs: vec![Pair::new("B".into(), "Charity".into())],
+            ..Default::default()
+        });
+        let solutions = solve(&input);
+        assert!(solutions
+            .iter()
+            .any(|pairs| pairs.contains(&Pair::new("A".into(), "Charity".into()))));
+        for pairs in solutions {
+            let gifts = input.gifts(pairs);
+            assert_eq!(gifts.len(), 3);
+            assert_eq!(gifts.iter().filter(|p| p.receiver == "Charity").count(), 1);
+            assert!(gifts.iter().all(|p| p.giver != "Charity"));
+            let draw = Draw {
+                pairs: gifts,
+                ..Default::default()
+            };
+            let msgs = compose_messages(&[draw], &input);
+            assert!(msgs.iter().all(|msg| !msg.email.contains("charity")));
+        }
+    }
+
+    #[test]
+    fn whitelist_is_public() {
+        let mut input = input(&["A", "B", "C"]);
+        assert_eq!(input.public_warning(), None);
+        input.whitelist.push(Pair::new("A".into(), "B".into()));
+        input.whitelist.push(Pair::new("B".into(), "C".into()));
+        let warning = input.public_warning().unwrap();
+        assert!(warning.contains("A gives to B"));
+        assert!(warning.contains("B gives to C"));
+    }
+
+    #[test]
+    fn reloaded_base_encoding() {
+        let mut input = input(&["A", "B", "C", "D", "E"]);
+        input.blacklist.push(Pair::new("A".into(), "B".into()));
+        input.history.push(Solution {
+            year: 2023,
+            exclude_pairs: true,
+            pairs: vec![Pair::new("C".into(), "D".into())],
+            ..Default::default()
+        });
+        let saved = ron::ser::to_string(&BaseEncoding::new(input.names(), false, None)).unwrap();
+        let base: BaseEncoding = ron::de::from_str(&saved).unwrap();
+        let sorted = |solutions: Vec<Vec<Pair<String>>>| {
+            let mut solutions: Vec<Vec<String>> = solutions
+                .into_iter()
+                .map(|pairs| {
+                    let mut pairs: Vec<String> = pairs.iter().map(|p| format!("{p:?}")).collect();
+                    pairs.sort();
+                    pairs
+                })
+                .collect();
+            solutions.sort();
+            solutions
+        };
+        assert_eq!(
+            sorted(solve_from(
+                &input,
+                base.into_encoder::<cadical::Solver>(),
+                Variety::Exact,
+                100,
+                &mut Timings::default()
+            )),
+            sorted(solve(&input))
+        );
+    }
+
+    #[test]
+    fn base_encoding_rewritten() {
+        let path = std::env::temp_dir().join(format!("base-{}.ron", std::process::id()));
+        let input = input(&["A", "B", "C", "D"]);
+        let count = |encoder: CadicalEncoder<Pair<String>>| {
+            solve_from(
+                &input,
+                encoder,
+                Variety::Exact,
+                100,
+                &mut Timings::default(),
+            )
+            .len()
+        };
+        let saved = |path: &Path| -> BaseEncoding {
+            ron::de::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+        };
+        let expected = solve(&input).len();
+        for contents in [
+            "not an encoding".to_string(),
+            ron::ser::to_string(&BaseEncoding::new(vec!["Z".into()], false, None)).unwrap(),
+        ] {
+            std::fs::write(&path, contents).unwrap();
+            assert_eq!(count(base_encoder(&input, &path)), expected);
+            assert_eq!(saved(&path).people, input.names());
+        }
+        assert_eq!(count(base_encoder(&input, &path)), expected);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn exact_cycles_chosen() {
+        let mut input = input(&["A", "B", "C", "D", "E", "F", "G"]);
+        input.rules.exact_cycles = Some(2);
+        assert!(input.validate().is_ok());
+        let draws = draw_default(&input);
+        assert_eq!(cycles(&draws[0].pairs).len(), 2);
+    }
+
+    #[test]
+    fn exact_cycles_too_many() {
+        let mut input = input(&["A", "B", "C", "D", "E", "F", "G"]);
+        input.rules.exact_cycles = Some(3);
+        assert_eq!(
+            input.validate().unwrap_err().to_string(),
+            "Exact cycles 3 not possible with 7 people; at most 2 cycles."
+        );
+    }
+
+    #[test]
+    fn exact_cycles_with_mutual() {
+        let mut input = input(&["A", "B", "C", "D"]);
+        input.allow_mutual = true;
+        input.rules.exact_cycles = Some(2);
+        assert!(input.validate().is_ok());
+        input
+            .different_cycle_sets
+            .push(vec!["A".into(), "B".into()]);
+        assert!(input.validate().is_ok());
+        let draws = draw_default(&input);
+        assert_eq!(cycles(&draws[0].pairs).len(), 2);
+    }
+
+    #[test]
+    fn past_gifts_in_message() {
+        let mut input = input(&["A", "B", "C"]);
+        let mut book = Pair::new("A".into(), "B".into());
+        book.gift = Some(String::from("a book"));
+        let mut socks = Pair::new("A".into(), "C".into());
+        socks.gift = Some(String::from("socks"));
+        for (year, pair) in [(2023, book), (2024, socks)] {
+            input.history.push(Solution {
+                year,
+                exclude_pairs: false,
+                pairs: vec![pair],
+                ..Default::default()
+            });
+        }
+        input.history.push(Solution {
+            year: 2022,
+            exclude_pairs: false,
+            pairs: vec![Pair::new("B".into(), "A".into())],
+            ..Default::default()
+        });
+        let draw = Draw::default();
+        let a = compose_message(&Pair::new("A".into(), "C".into()), &input, &draw).unwrap();
+        assert!(a
+            .body
+            .contains("You previously gave: a book (2023), and socks (2024)."));
+        let b = compose_message(&Pair::new("B".into(), "C".into()), &input, &draw).unwrap();
+        assert!(!b.body.contains("You previously gave"));
+    }
+
+    #[test]
+    fn repeated_cycle_rejected() {
+        let pairs = |names: &[(&str, &str)]| -> Vec<Pair<String>> {
+            names
+                .iter()
+                .map(|(g, r)| Pair::new(g.to_string(), r.to_string()))
+                .collect()
+        };
+        let history = vec![
+            Solution {
+                year: 2024,
+                exclude_pairs: false,
+                pairs: pairs(&[
+                    ("A", "B"),
+                    ("B", "C"),
+                    ("C", "A"),
+                    ("D", "E"),
+                    ("E", "F"),
+                    ("F", "D"),
+                ]),
+                ..Default::default()
+            },
+            Solution {
+                year: 2023,
+                exclude_pairs: false,
+                pairs: pairs(&[
+                    ("A", "D"),
+                    ("D", "B"),
+                    ("B", "A"),
+                    ("C", "E"),
+                    ("E", "F"),
+                    ("F", "C"),
+                ]),
+                ..Default::default()
+            },
+        ];
+        // Same A→B→C→A cycle, different D, E, F cycle.
+        let repeat = pairs(&[
+            ("B", "C"),
+            ("C", "A"),
+            ("A", "B"),
+            ("D", "F"),
+            ("F", "E"),
+            ("E", "D"),
+        ]);
+        assert!(repeats_cycle(&repeat, &history, 1));
+        // Only repeats a cycle from 2023.
+        let older = pairs(&[
+            ("B", "A"),
+            ("A", "D"),
+            ("D", "B"),
+            ("C", "F"),
+            ("F", "E"),
+            ("E", "C"),
+        ]);
+        assert!(!repeats_cycle(&older, &history, 1));
+        assert!(repeats_cycle(&older, &history, 2));
+    }
+
+    #[test]
+    fn reversal_never_chosen() {
+        let mut input = input(&["A", "B", "C"]);
+        let last = vec![
+            Pair::new("A".into(), "B".into()),
+            Pair::new("B".into(), "C".into()),
+            Pair::new("C".into(), "A".into()),
+        ];
+        input.history.push(Solution {
+            year: 2024,
+            exclude_pairs: false,
+            pairs: last.clone(),
+            ..Default::default()
+        });
+        input.rules.forbid_reversal = true;
+        // With three people the only other solution is the reversal.
+        let mut not_reversed = CheckEvery(|pairs: &[Pair<String>]| cycles(pairs) == cycles(&last));
+        draw_with(&input, None, &mut not_reversed).unwrap();
+    }
+
+    #[test]
+    fn reversed_pairs_limited() {
+        let pairs = |names: &[(&str, &str)]| -> Vec<Pair<String>> {
+            names
+                .iter()
+                .map(|(g, r)| Pair::new(g.to_string(), r.to_string()))
+                .collect()
+        };
+        let history = vec![Solution {
+            year: 2024,
+            exclude_pairs: false,
+            pairs: pairs(&[("A", "B"), ("B", "C"), ("C", "D"), ("D", "A")]),
+            ..Default::default()
+        }];
+        let reversal = pairs(&[("B", "A"), ("C", "B"), ("D", "C"), ("A", "D")]);
+        assert!(reverses_latest(&reversal, &history, None));
+        // B→A and D→C are reversed; A→B and C→D aren't.
+        let similar = pairs(&[("B", "A"), ("A", "B"), ("D", "C"), ("C", "D")]);
+        assert!(!reverses_latest(&similar, &history, None));
+        assert!(!reverses_latest(&similar, &history, Some(2)));
+        assert!(reverses_latest(&similar, &history, Some(1)));
+        assert!(!reverses_latest(&reversal, &[], None));
+
+        // A charity's pairs don't count.
+        let mut input = input(&["A", "B", "C", "D", "E", "Charity"]);
+        input.people[5].charity = true;
+        let mut history = history;
+        history[0]
+            .pairs
+            .extend(pairs(&[("E", "Charity"), ("Charity", "E")]));
+        let similar = pairs(&[
+            ("B", "A"),
+            ("A", "B"),
+            ("D", "C"),
+            ("C", "D"),
+            ("Charity", "E"),
+            ("E", "Charity"),
+        ]);
+        assert!(reverses_latest(&similar, &history, Some(2)));
+        let similar = input.without_charities(&similar);
+        assert!(!reverses_latest(&similar, &history, Some(2)));
+    }
+
+    #[test]
+    fn dry_run_summary_without_exec() {
+        let input = input(&["A", "B"]);
+        let msgs: Vec<Message> = input
+            .people
+            .iter()
+            .map(|person| compose_reminder(person, &input.footer()))
+            .collect();
+        assert_eq!(
+            dry_run_summary(&msgs, false),
+            "Composed 2 messages; no delivery command given."
+        );
+        assert!(dry_run_summary(&msgs, true).ends_with("\n  A <a@email.com>\n  B <b@email.com>"));
+        let block = dry_run_message(&msgs[0]);
+        assert!(block.starts_with("----- To: A <a@email.com>\nSubject: "));
+        assert!(block.contains(&msgs[0].body));
+        assert!(block.ends_with("----- End of message\n"));
+    }
+
+    #[test]
+    fn dry_run_hides_messages() {
+        let input = input(&["A", "B"]);
+        let pair = Pair::new("A".into(), "B".into());
+        let msg = compose_message(&pair, &input, &Draw::default()).unwrap();
+        let mut delivery = Delivery {
+            exec: vec!["bin/mail-app  -v"],
+            contact_exec: &[],
+            smtp: None,
+            dry_run: true,
+            verbose: false,
+        };
+        assert_eq!(
+            delivery.dry_run_output(&msg),
+            format!("bin/mail-app -v -s {} A <a@email.com>\n", msg.subject)
+        );
+        delivery.verbose = true;
+        let output = delivery.dry_run_output(&msg);
+        assert!(output.starts_with(&dry_run_message(&msg)));
+        assert!(output.contains("for B."));
+
+        delivery.verbose = false;
+        delivery.smtp = Some(Smtp {
+            server: String::from("mail.example.com"),
+            port: 587,
+            username: None,
+            password: None,
+            from: String::from("Santa <santa@email.com>"),
+        });
+        assert_eq!(
+            delivery.dry_run_output(&msg),
+            "smtp://mail.example.com:587 from Santa <santa@email.com> to A <a@email.com>\n"
+        );
+    }
+
+    #[test]
+    fn cross_group_cycles() {
+        let mut input = input(&["A", "B", "C", "D", "E", "F"]);
+        for (person, team) in input.people.iter_mut().zip(["x", "x", "x", "y", "y", "y"]) {
+            person.attributes.insert("team".into(), team.into());
+        }
+        let pairs = |names: &[(&str, &str)]| -> Vec<Pair<String>> {
+            names
+                .iter()
+                .map(|(g, r)| Pair::new(g.to_string(), r.to_string()))
+                .collect()
+        };
+        let islands = pairs(&[
+            ("A", "B"),
+            ("B", "C"),
+            ("C", "A"),
+            ("D", "E"),
+            ("E", "F"),
+            ("F", "D"),
+        ]);
+        assert!(has_island(&islands, &input.people, &[]));
+        let mixed = pairs(&[
+            ("A", "D"),
+            ("D", "B"),
+            ("B", "E"),
+            ("E", "C"),
+            ("C", "F"),
+            ("F", "A"),
+        ]);
+        assert!(!has_island(&mixed, &input.people, &[]));
+
+        input.rules.require_cross_group_cycles = true;
+        let draws = draw_default(&input);
+        assert!(!has_island(
+            &draws[0].pairs,
+            &input.people,
+            &input.households()
+        ));
+    }
+
+    #[test]
+    fn people_from_csv() {
+        let csv = "name,email,department\nAmy,amy@email.com,Sales\n\"Doe, John\",john@email.com,\n";
+        let people = parse_people_csv(csv.as_bytes()).unwrap();
+        assert_eq!(people.len(), 2);
+        assert_eq!(people[0].name, "Amy");
+        assert_eq!(people[0].email, "amy@email.com");
+        assert_eq!(people[0].attributes["department"], "Sales");
+        assert_eq!(people[1].name, "Doe, John");
+        assert!(people[1].attributes.is_empty());
+        assert!(parse_people_csv("name\nAmy\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn count_small_cases() {
+        assert_eq!(
+            count_solutions(&input(&["A", "B", "C"]), COUNT_CAP),
+            (2, true)
+        );
+        // Only the 4-cycles since no one gives to each other.
+        let mut input = input(&["A", "B", "C", "D"]);
+        assert_eq!(count_solutions(&input, COUNT_CAP), (6, true));
+        input.blacklist.push(Pair::new("A".into(), "B".into()));
+        assert_eq!(count_solutions(&input, COUNT_CAP), (4, true));
+        assert_eq!(count_solutions(&input, 3), (3, false));
+        assert_eq!(count_solutions(&input, 4), (4, true));
+    }
+
+    #[test]
+    fn feasibility_per_group() {
+        let mut input = input(&["A", "B", "C", "D", "E", "F"]);
+        let group = |label: &str, members: &[&str]| Group {
+            label: label.into(),
+            members: members.iter().map(|m| m.to_string()).collect(),
+        };
+        input.groups = vec![
+            group("one", &["A", "B", "C"]),
+            group("two", &["D", "E", "F"]),
+        ];
+        input.blacklist.push(Pair::new("D".into(), "E".into()));
+        input.blacklist.push(Pair::new("D".into(), "F".into()));
+        let found = feasibility(&input, &mut Timings::default());
+        assert_eq!(found, [(Some("one".into()), 2), (Some("two".into()), 0)]);
+    }
+
+    #[test]
+    fn large_roster_solves() {
+        let names: Vec<String> = (0..150).map(|i| format!("P{i}")).collect();
+        let input = Input {
+            people: names.iter().map(|name| person(name)).collect(),
+            ..Default::default()
+        };
+        let mut encoder = CadicalEncoder::new();
+        encode_input(&input, &mut encoder);
+        let pairs = encoder.solve().map(extract_pos).unwrap();
+        assert_eq!(pairs.len(), names.len());
+        let solution = Solution {
+            pairs: pairs.clone(),
+            ..Default::default()
+        };
+        assert!(solution.permutation_problems(1).is_empty());
+        for pair in &pairs {
+            assert!(!pairs.contains(&Pair::new(pair.receiver.clone(), pair.giver.clone())));
+        }
+    }
+
+    #[test]
+    fn recent_repeats_cost_more() {
+        let history = vec![
+            Solution {
+                year: 2024,
+                exclude_pairs: false,
+                pairs: vec![Pair::new("A".into(), "B".into())],
+                ..Default::default()
+            },
+            Solution {
+                year: 2023,
+                exclude_pairs: false,
+                pairs: vec![Pair::new("B".into(), "C".into())],
+                ..Default::default()
+            },
+        ];
+        let recent = vec![Pair::new("A".into(), "B".into())];
+        let older = vec![Pair::new("B".into(), "C".into())];
+        assert_eq!(history_penalty(&recent, &history, 0.5), 1.0);
+        assert_eq!(history_penalty(&older, &history, 0.5), 0.5);
+        let mut solutions = vec![recent, older.clone()];
+        retain_best(&mut solutions, |pairs| {
+            history_penalty(pairs, &history, 0.5)
+        });
+        assert_eq!(solutions, [older]);
+    }
+
+    #[test]
+    fn empty_lists_change_nothing() {
+        let input = input(&["A", "B", "C", "D"]);
+        let all = |mut encoder: CadicalEncoder<Pair<String>>| {
+            let mut solutions = vec![];
+            while let Some(pairs) = encoder.solve().map(extract_pos) {
+                exclude_some_pairs(pairs.iter().cloned(), &mut encoder);
+                let mut pairs: Vec<String> = pairs.iter().map(|p| format!("{p:?}")).collect();
+                pairs.sort();
+                solutions.push(pairs);
+            }
+            solutions.sort();
+            solutions
+        };
+        let mut guarded = CadicalEncoder::new();
+        encode_input(&input, &mut guarded);
+        let mut unguarded = CadicalEncoder::new();
+        encode_input(&input, &mut unguarded);
+        let empty = std::iter::empty::<Pair<String>>();
+        unguarded.add_constraint(And(empty.clone()));
+        unguarded.add_constraint(Not(Or(empty)));
+        let solutions = all(guarded);
+        assert_eq!(solutions.len(), 6);
+        assert_eq!(solutions, all(unguarded));
+    }
+
+    #[test]
+    fn draw_in_memory() {
+        let input = input(&["A", "B", "C", "D"]);
+        let options = DrawOptions {
+            variety: Variety::Variations,
+            with_fallback: true,
+            ..Default::default()
+        };
+        let draws = draw(
+            &input,
+            &options,
+            None,
+            &mut RandomSelector(seeded_rng(Some(1))),
+            &mut AuditEntry::default(),
+            &mut Timings::default(),
+        )
+        .unwrap();
+        let msgs = compose_messages(&draws, &input);
+        assert_eq!(msgs.len(), 4);
+        for (pair, msg) in draws[0].pairs.iter().zip(&msgs) {
+            assert!(msg
+                .email
+                .contains(&format!("{}@email.com", pair.giver.to_lowercase())));
+            assert!(msg.body.contains(&pair.receiver));
+        }
+    }
+
+    #[test]
+    fn no_short_cycles() {
+        let mut count = 0;
+        visit_cycles(5, 3, &mut vec![], &mut |_| count += 1);
+        // 5 choose 3 people, each in 2 directions.
+        assert_eq!(count, 20);
+
+        let mut input = input(&["A", "B", "C", "D", "E", "F", "G"]);
+        input.rules.min_cycle_length = Some(5);
+        let solutions = solve_variety(&input, Variety::Variations);
+        assert!(!solutions.is_empty());
+        for pairs in &solutions {
+            assert!(cycles(pairs).iter().all(|cycle| cycle.len() >= 5));
+        }
+        assert!(solutions.iter().any(|pairs| cycles(pairs).len() == 1));
+    }
+
+    #[test]
+    fn no_short_cycles_with_mutual() {
+        let mut input = input(&["A", "B", "C", "D", "E", "F"]);
+        input.allow_mutual = true;
+        input.rules.min_cycle_length = Some(3);
+        let solutions = solve_variety(&input, Variety::Variations);
+        assert!(!solutions.is_empty());
+        for pairs in &solutions {
+            assert!(cycles(pairs).iter().all(|cycle| cycle.len() >= 3));
+        }
+    }
+
+    #[test]
+    fn min_displacement_in_order() {
+        let mut input = input(&["A", "B", "C", "D", "E", "F", "G", "H"]);
+        input.order = ["H", "G", "F", "E", "D", "C", "B", "A"]
+            .iter()
+            .map(|n| n.to_string())
+            .collect();
+        input.rules.min_displacement = Some(2);
+        assert!(input.validate().is_ok());
+        let position = |name: &str| input.order.iter().position(|n| n == name).unwrap();
+        let solutions = solve_variety(&input, Variety::Variations);
+        assert!(!solutions.is_empty());
+        for pair in solutions.iter().flatten() {
+            assert!(position(&pair.giver).abs_diff(position(&pair.receiver)) > 2);
+        }
+    }
+
+    #[test]
+    fn min_displacement_too_large() {
+        let mut input = input(&["A", "B", "C", "D", "E", "F", "G"]);
+        input.rules.min_displacement = Some(3);
+        assert_eq!(
+            input.validate().unwrap_err().to_string(),
+            "Minimum displacement 3 not possible with 7 people in order; at most 2."
+        );
+        input.rules.min_displacement = Some(2);
+        input.order.push(String::from("Z"));
+        assert_eq!(
+            input.validate(),
+            Err(InputError::UnknownOrderName(String::from("Z")))
+        );
+    }
+
+    /// Chooses the solution with the fewest cycles, the first of those.
+    struct FewestCycles;
+
+    impl Selector for FewestCycles {
+        fn select(&mut self, solutions: &[Vec<Pair<String>>]) -> usize {
+            (0..solutions.len())
+                .min_by_key(|&i| cycles(&solutions[i]).len())
+                .unwrap()
+        }
+    }
+
+    #[test]
+    fn selector_chooses() {
+        let input = input(&["A", "B", "C", "D", "E", "F"]);
+        let solutions = solve_variety(&input, Variety::Variations);
+        let expected = &solutions[FewestCycles.select(&solutions)];
+        assert_eq!(cycles(expected).len(), 1);
+        let draws = draw(
+            &input,
+            &DrawOptions {
+                variety: Variety::Variations,
+                ..Default::default()
+            },
+            None,
+            &mut FewestCycles,
+            &mut AuditEntry::default(),
+            &mut Timings::default(),
+        )
+        .unwrap();
+        let mut expected = expected.clone();
+        expected.sort_by(|a, b| a.giver.cmp(&b.giver));
+        assert_eq!(draws[0].pairs, expected);
+    }
+
+    #[test]
+    fn max_solutions_bounds_search() {
+        let input = input(&["A", "B", "C", "D", "E", "F"]);
+        let mut audit = AuditEntry::default();
+        let options = DrawOptions {
+            variety: Variety::Variations,
+            max_solutions: 3,
+            ..Default::default()
+        };
+        draw(
+            &input,
+            &options,
+            None,
+            &mut RandomSelector(seeded_rng(Some(1))),
+            &mut audit,
+            &mut Timings::default(),
+        )
+        .unwrap();
+        assert_eq!(audit.solutions, 3);
+    }
+
+    #[test]
+    fn verify_solution_catches_invalid() {
+        let people: Vec<String> = ["A", "B", "C"].iter().map(|n| n.to_string()).collect();
+        let pairs = |pairs: &[(&str, &str)]| -> Vec<Pair<String>> {
+            pairs
+                .iter()
+                .map(|(giver, receiver)| Pair::new(giver.to_string(), receiver.to_string()))
+                .collect()
+        };
+        assert_eq!(
+            verify_solution(&people, 1, &pairs(&[("A", "B"), ("B", "C"), ("C", "A")])),
+            Ok(())
+        );
+        assert_eq!(
+            verify_solution(&people, 1, &pairs(&[("A", "A"), ("B", "C"), ("C", "B")])),
+            Err(String::from("'A' gives to themselves!"))
+        );
+        assert_eq!(
+            verify_solution(&people, 1, &pairs(&[("A", "B"), ("A", "C"), ("C", "A")])),
+            Err(String::from("'A' gives 2 times instead of once!"))
+        );
+        assert_eq!(
+            verify_solution(&people, 1, &pairs(&[("A", "B"), ("B", "C"), ("C", "B")])),
+            Err(String::from("'A' receives 0 times instead of once!"))
+        );
+    }
+
+    #[test]
+    fn require_count() {
+        let input = input(&["A", "B", "C", "D"]);
+        let found = solve(&input).len();
+        assert!(found < 100);
+        let draw = |count| {
+            let options = DrawOptions {
+                require_count: Some(count),
+                ..Default::default()
+            };
+            draw(
+                &input,
+                &options,
+                None,
+                &mut RandomSelector(seeded_rng(Some(1))),
+                &mut AuditEntry::default(),
+                &mut Timings::default(),
+            )
+        };
+        assert!(draw(found).is_ok());
+        assert_eq!(
+            draw(found + 1),
+            Err(format!(
+                "Only {found} solutions found but {} are required!",
+                found + 1
+            ))
+        );
+    }
+
+    #[test]
+    fn seed_from_env() {
+        std::env::set_var("SECRET_SANTA_SEED", "42");
+        assert_eq!(env_seed(), Ok(Some(42)));
+        let input = input(&["A", "B", "C", "D", "E", "F"]);
+        let run = || {
+            draw(
+                &input,
+                &DrawOptions::default(),
+                None,
+                &mut RandomSelector(seeded_rng(env_seed().unwrap())),
+                &mut AuditEntry::default(),
+                &mut Timings::default(),
+            )
+            .unwrap()
+        };
+        assert_eq!(run(), run());
+        std::env::set_var("SECRET_SANTA_SEED", "-1");
+        assert!(env_seed().is_err());
+        std::env::remove_var("SECRET_SANTA_SEED");
+        assert_eq!(env_seed(), Ok(None));
+    }
+
+    #[test]
+    fn history_message_limit() {
+        let mut input = input(&["A", "B", "C", "D", "E"]);
+        for (year, receiver) in [(2024, "B"), (2023, "C"), (2022, "D"), (2021, "E")] {
+            input.history.push(Solution {
+                year,
+                exclude_pairs: false,
+                pairs: vec![Pair::new("A".into(), receiver.into())],
+                ..Default::default()
+            });
+        }
+        let pair = Pair::new("A".into(), "C".into());
+        let body = |input: &Input| {
+            compose_message(&pair, input, &Draw::default())
+                .unwrap()
+                .body
+        };
+        assert!(body(&input).contains("B (2024), C (2023), D (2022), and E (2021)."));
+        input.history_message_limit = Some(4);
+        assert!(body(&input).contains("B (2024), C (2023), D (2022), and E (2021)."));
+        input.history_message_limit = Some(2);
+        assert!(body(&input).contains("for B (2024), C (2023), …and 2 earlier."));
+    }
+
+    #[test]
+    fn max_body_length() {
+        let mut input = input(&["A", "B", "C"]);
+        let mut socks = Pair::new("A".into(), "B".into());
+        socks.gift = Some(String::from("socks"));
+        input.history.push(Solution {
+            year: 2024,
+            exclude_pairs: false,
+            pairs: vec![socks, Pair::new("C".into(), "A".into())],
+            ..Default::default()
+        });
+        let pair = Pair::new("A".into(), "C".into());
+        let body = |input: &Input| {
+            compose_message(&pair, input, &Draw::default())
+                .unwrap()
+                .body
+        };
+        let full = body(&input);
+        let count = |body: &str| body.chars().count();
+        input.max_body_length = Some(count(&full));
+        assert_eq!(body(&input), full);
+
+        // Room for the note but not the past receivers.
+        let receivers = "\nYou were Secret Santa for B (2024).\n";
+        assert!(full.contains(receivers));
+        let max = count(&full) - count(receivers) + count(TRUNCATED);
+        input.max_body_length = Some(max);
+        let cut = body(&input);
+        assert_eq!(count(&cut), max);
+        assert!(!cut.contains("You were Secret Santa for"));
+        assert!(cut.contains("Christmases past: C (2024)."));
+        assert!(cut.contains("You previously gave: socks"));
+        assert!(cut.contains(TRUNCATED));
+
+        input.max_body_length = Some(count(&cut) - 1);
+        let cut = body(&input);
+        assert!(!cut.contains("Christmases past"));
+        assert!(cut.contains("You previously gave: socks"));
+
+        input.max_body_length = Some(1);
+        let cut = body(&input);
+        assert!(cut.starts_with("A, you are the Secret Santa for C.\n"));
+        assert!(!cut.contains("You previously gave"));
+        assert!(cut.contains(TRUNCATED));
+    }
+
+    #[test]
+    fn receiver_santas_in_message() {
+        let mut input = input(&["A", "B", "C", "D"]);
+        for (year, giver) in [(2024, "C"), (2023, "D")] {
+            input.history.push(Solution {
+                year,
+                exclude_pairs: false,
+                pairs: vec![Pair::new(giver.into(), "B".into())],
+                ..Default::default()
+            });
+        }
+        let pair = Pair::new("A".into(), "B".into());
+        let msg = compose_message(&pair, &input, &Draw::default()).unwrap();
+        assert!(msg.body.contains(
+            "\nB had these Secret Santas in Christmases past: C (2024), and D (2023).\n"
+        ));
+        assert!(!msg.body.contains("You had these Secret Santas"));
+
+        input.template = Some(String::from("{receiver}: {receiver_past_givers}"));
+        let msg = compose_message(&pair, &input, &Draw::default()).unwrap();
+        assert!(msg.body.starts_with("B: C (2024), and D (2023)\n"));
+    }
+
+    #[test]
+    fn event_in_message() {
+        let mut input = input(&["A", "B", "C"]);
+        let pair = Pair::new("A".into(), "B".into());
+        let body = |input: &Input| {
+            compose_message(&pair, input, &Draw::default())
+                .unwrap()
+                .body
+        };
+        let plain = body(&input);
+        assert!(!plain.contains("gift exchange"));
+        input.event_date = Some(String::from("December 20th at 7pm"));
+        assert!(body(&input).starts_with(
+            "A, you are the Secret Santa for B.\n\nThe gift exchange is December 20th at 7pm.\n"
+        ));
+        input.event_location = Some(String::from("Grandma's"));
+        assert!(
+            body(&input).contains("\nThe gift exchange is December 20th at 7pm at Grandma's.\n")
+        );
+        input.event_date = None;
+        assert!(body(&input).contains("\nThe gift exchange is at Grandma's.\n"));
+        input.event_location = None;
+        assert_eq!(body(&input), plain);
+    }
+
+    #[test]
+    fn budget_in_message() {
+        let mut input = input(&["A", "B", "C"]);
+        let pair = Pair::new("A".into(), "B".into());
+        let body = |input: &Input| {
+            compose_message(&pair, input, &Draw::default())
+                .unwrap()
+                .body
+        };
+        assert!(!body(&input).contains("Suggested spend"));
+        input.budget = Some(String::from("€20"));
+        assert!(body(&input).contains("\nSuggested spend: €20.\n"));
+    }
+
+    #[test]
+    fn receiver_wishlist_in_message() {
+        let mut input = input(&["A", "B", "C"]);
+        input.people[2].wishlist = vec![String::from("socks"), String::from("a book")];
+        let body = |input: &Input, receiver: &str| {
+            let pair = Pair::new("A".into(), receiver.into());
+            compose_message(&pair, input, &Draw::default())
+                .unwrap()
+                .body
+        };
+        let wishlist = "\nHere's what C is hoping for: socks, and a book.\n";
+        assert!(body(&input, "C").contains(wishlist));
+        assert!(!body(&input, "B").contains("hoping for"));
+
+        // The history is left out to fit before the wishlist is.
+        input.history.push(Solution {
+            year: 2024,
+            exclude_pairs: false,
+            pairs: vec![Pair::new("A".into(), "B".into())],
+            ..Default::default()
+        });
+        let full = body(&input, "C");
+        let past = "\nYou were Secret Santa for B (2024).\n";
+        assert!(full.contains(past));
+        let count = |text: &str| text.chars().count();
+        input.max_body_length = Some(count(&full) - count(past) + count(TRUNCATED));
+        let cut = body(&input, "C");
+        assert!(!cut.contains(past));
+        assert!(cut.contains(wishlist));
+    }
+
+    #[test]
+    fn template_body() {
+        let mut input = input(&["A", "B", "C"]);
+        input.history.push(Solution {
+            year: 2024,
+            exclude_pairs: false,
+            pairs: vec![Pair::new("A".into(), "B".into())],
+            ..Default::default()
+        });
+        input.budget = Some(String::from("$25"));
+        input
+            .set_template(String::from(
+                "Hi {giver}! Give {receiver} a gift of {budget}.\n\
+                 Before: {past_receivers}. Gifts: {past_gifts}. { }",
+            ))
+            .unwrap();
+        let pair = Pair::new("A".into(), "C".into());
+        let msg = compose_message(&pair, &input, &Draw::default()).unwrap();
+        assert!(msg
+            .body
+            .starts_with("Hi A! Give C a gift of $25.\nBefore: B (2024). Gifts: . { }\n"));
+        assert!(msg.body.ends_with(&format!("{}\n", input.footer())));
+    }
+
+    #[test]
+    fn template_unknown_placeholder() {
+        let mut input = input(&["A", "B"]);
+        assert_eq!(
+            input.set_template(String::from("Hi {giver}, give to {reciever}.")),
+            Err(String::from(
+                "Placeholder '{reciever}' in template is not one of: {giver}, {receiver}, \
+                 {group}, {fallback}, {past_receivers}, {past_givers}, \
+                 {receiver_past_givers}, {past_gifts}, {wishlist}, {budget}, {event_date}, {event_location}."
+            ))
+        );
+    }
+
+    #[test]
+    fn people_report_rows() {
+        let mut input = input(&["A", "B", "C", "D"]);
+        input.blacklist_sets.push(vec!["A".into(), "B".into()]);
+        let mut feud = Pair::new("C".into(), "D".into());
+        feud.reason = Some(String::from("feud"));
+        input.blacklist.push(feud);
+        input.people[0].attributes.insert("team".into(), "x".into());
+        input.history.push(Solution {
+            year: 2024,
+            exclude_pairs: false,
+            pairs: vec![Pair::new("A".into(), "C".into())],
+            ..Default::default()
+        });
+        let mut report = vec![];
+        people_report(&input, &mut report).unwrap();
+        let report = String::from_utf8(report).unwrap();
+        let rows: Vec<&str> = report.lines().collect();
+        assert_eq!(rows.len(), 5);
+        assert_eq!(
+            rows[0],
+            "name,households,attributes,blacklist,whitelist,feasible_receivers,past_receivers,past_santas,reasons"
+        );
+        assert_eq!(rows[1], "A,A B,team=x,0,0,2,C (2024),,");
+        assert_eq!(rows[3], "C,,,1,0,2,,A (2024),C -> D: feud");
+    }
+
+    #[test]
+    fn reasons_explained_not_solved() {
+        let mut input = input(&["A", "B", "C", "D", "E"]);
+        input.blacklist.push(Pair::new("A".into(), "B".into()));
+        input.whitelist.push(Pair::new("C".into(), "D".into()));
+        let sorted = |solutions: Vec<Vec<Pair<String>>>| -> BTreeSet<BTreeSet<(String, String)>> {
+            solutions
+                .into_iter()
+                .map(|pairs| pairs.into_iter().map(|p| (p.giver, p.receiver)).collect())
+                .collect()
+        };
+        let before = sorted(solve(&input));
+        input.blacklist[0].reason = Some(String::from("they live together"));
+        input.blacklist[0].until_year = Some(2030);
+        input.whitelist[0].reason = Some(String::from("D asked for C"));
+        assert_eq!(sorted(solve(&input)), before);
+        assert_eq!(
+            explain(&input),
+            "Whitelist:\n  C must give to D: D asked for C\n\
+             Blacklist:\n  A may not give to B until 2030: they live together\n"
+        );
+    }
+
+    #[test]
+    fn organizer_digest() {
+        let statuses = vec![
+            (String::from("A <a@email.com>"), Ok(())),
+            (
+                String::from("B <b@email.com>"),
+                Err(String::from("no route")),
+            ),
+        ];
+        let draws = vec![Draw {
+            pairs: vec![Pair::new("A".into(), "B".into())],
+            ..Default::default()
+        }];
+        let digest = compose_digest("boss@email.com", &statuses, None, "");
+        assert_eq!(digest.email, "boss@email.com");
+        assert!(digest.body.contains("Sent 1 of 2 messages."));
+        assert!(digest.body.contains("Sent: A <a@email.com>"));
+        assert!(digest.body.contains("Failed: B <b@email.com> (no route)"));
+        assert!(!digest.body.contains("gives to"));
+        let digest = compose_digest("boss@email.com", &statuses, Some(&draws), "");
+        assert!(digest.body.contains("A gives to B"));
+    }
+
+    #[test]
+    fn different_cycle_sets() {
+        let mut input = input(&["A", "B", "C", "D", "E", "F", "G"]);
+        input
+            .different_cycle_sets
+            .push(vec!["A".into(), "B".into()]);
+        assert!(input.validate().is_ok());
+        let draws = draw_default(&input);
+        let cycles = cycles(&draws[0].pairs);
+        let cycle_of = |name: &str| cycles.iter().position(|c| c.iter().any(|n| n == name));
+        assert_ne!(cycle_of("A"), cycle_of("B"));
+        for pairs in solve(&input) {
+            let cycles = crate::cycles(&pairs);
+            assert!(cycles
+                .iter()
+                .all(|c| !(c.contains(&"A".into()) && c.contains(&"B".into()))));
+        }
+    }
+
+    #[test]
+    fn different_cycle_sets_too_large() {
+        let mut input = input(&["A", "B", "C", "D", "E", "F", "G"]);
+        input
+            .different_cycle_sets
+            .push(vec!["A".into(), "B".into(), "C".into()]);
+        assert_eq!(
+            input.validate().unwrap_err().to_string(),
+            "Different cycle set [\"A\", \"B\", \"C\"] needs 3 cycles; at most 2 possible."
+        );
+        input.different_cycle_sets = vec![vec!["A".into(), "Z".into()]];
+        assert_eq!(
+            input.validate(),
+            Err(InputError::UnknownCycleSetName(String::from("Z")))
+        );
+    }
+
+    #[test]
+    fn variety_strategies() {
+        let input = input(&["A", "B", "C", "D", "E", "F"]);
+        let valid = |pairs: &Vec<Pair<String>>| {
+            let solution = Solution {
+                year: 2025,
+                exclude_pairs: false,
+                pairs: pairs.clone(),
+                ..Default::default()
+            };
+            solution.permutation_problems(1).is_empty()
+                && pairs.iter().all(|p| p.giver != p.receiver)
+        };
+
+        let exact = solve(&input);
+        assert!(exact.iter().all(valid));
+        for (i, a) in exact.iter().enumerate() {
+            for b in &exact[i + 1..] {
+                assert_eq!(overlap(a, b), 0);
+            }
+        }
+
+        let variations = solve_variety(&input, Variety::Variations);
+        assert!(variations.iter().all(valid));
+        assert!(variations.len() > exact.len());
+        for (i, a) in variations.iter().enumerate() {
+            for b in &variations[i + 1..] {
+                assert!(overlap(a, b) < a.len());
+            }
+        }
+    }
+
+    #[test]
+    fn profile_changes_rules() {
+        let mut input = input(&["A", "B", "C", "D", "E", "F"]);
+        input.profiles.insert(
+            String::from("strict"),
+            Rules {
+                exact_cycles: Some(2),
+                ..Default::default()
+            },
+        );
+        input
+            .profiles
+            .insert(String::from("relaxed"), Rules::default());
+        let draw_cycles = |input: &Input| cycles(&draw_default(input)[0].pairs).len();
+        input.use_profile("strict").unwrap();
+        assert_eq!(input.rules.exact_cycles, Some(2));
+        assert_eq!(draw_cycles(&input), 2);
+        input.use_profile("relaxed").unwrap();
+        assert_eq!(input.rules.exact_cycles, None);
+    }
+
+    #[test]
+    fn unknown_profile() {
+        assert_eq!(
+            input(&["A", "B", "C"]).use_profile("lax"),
+            Err(InputError::UnknownProfile(String::from("lax")))
+        );
+    }
+
+    #[test]
+    fn tokens_verify() {
+        let input = input(&["A", "B"]);
+        let mut msgs = vec![compose_reminder(&input.people[0], &input.footer())];
+        add_tokens(&mut msgs, b"secret");
+        let token = msgs[0]
+            .body
+            .trim_end()
+            .rsplit_once("Verification token: ")
+            .unwrap()
+            .1;
+        assert!(verify_token(b"secret", "A <a@email.com>", token));
+        assert!(!verify_token(b"secret", "B <b@email.com>", token));
+        assert!(!verify_token(b"other", "A <a@email.com>", token));
+        let mut tampered = token.to_string();
+        tampered.replace_range(0..1, if token.starts_with('0') { "1" } else { "0" });
+        assert!(!verify_token(b"secret", "A <a@email.com>", &tampered));
+        assert!(!verify_token(b"secret", "A <a@email.com>", "not hex"));
+    }
+
+    #[test]
+    fn reveal_page_hides_assignments() {
+        let input = input(&["Amy", "Bob", "Cal"]);
+        let draws = vec![Draw {
+            pairs: solve(&input).swap_remove(0),
+            ..Default::default()
+        }];
+        let msgs = compose_messages(&draws, &input);
+        let page = reveal_page(&msgs, b"secret");
+        assert!(!page.contains("Secret Santa for"));
+        for msg in &msgs {
+            let token = token(b"secret", &msg.email);
+            let id = sha256_hex(format!("id:{token}").as_bytes());
+            let (_, rest) = page.split_once(&format!("\"{id}\":\"")).unwrap();
+            let (blob, _) = rest.split_once('"').unwrap();
+            let mut bytes: Vec<u8> = (0..blob.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&blob[i..i + 2], 16).unwrap())
+                .collect();
+            xor_keystream(&token, &mut bytes);
+            assert_eq!(String::from_utf8(bytes).unwrap(), msg.body);
+            let sent = token_message(msg, b"secret", &input.footer());
+            assert!(sent.body.contains(&token));
+            assert!(!sent.body.contains("Secret Santa for"));
+        }
+    }
+
+    #[test]
+    fn write_default_refuses_overwrite() {
+        let path = std::env::temp_dir().join(format!("default-{}.ron", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        write_new(&path, "first", false).unwrap();
+        let e = write_new(&path, "second", false).unwrap_err();
+        assert!(e.to_string().contains("--force"));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "first");
+        write_new(&path, "third", true).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "third");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn avoid_next_excluded() {
+        let mut input = input(&["A", "B", "C", "D"]);
+        let avoid = Pair::new(String::from("A"), String::from("B"));
+        input.history.push(Solution {
+            year: 2023,
+            exclude_pairs: false,
+            pairs: vec![],
+            avoid_next: vec![Pair::new("C".into(), "D".into())],
+        });
+        input.history.push(Solution {
+            year: 2024,
+            exclude_pairs: false,
+            pairs: vec![],
+            avoid_next: vec![avoid.clone()],
+        });
+        let solutions = solve_variety(&input, Variety::Variations);
+        assert!(solutions.iter().all(|pairs| !pairs.contains(&avoid)));
+        // Only the latest year's feedback applies.
+        assert!(solutions
+            .iter()
+            .any(|pairs| pairs.contains(&Pair::new("C".into(), "D".into()))));
+    }
+
+    #[test]
+    fn history_lookback_overrides_flags() {
+        let mut input = input(&["A", "B", "C", "D"]);
+        let pair = |giver: &str, receiver: &str| Pair::new(giver.into(), receiver.into());
+        for (year, exclude_pairs, pair) in [
+            (2022, true, pair("A", "B")),
+            (2023, false, pair("B", "C")),
+            (2024, false, pair("C", "D")),
+        ] {
+            input.history.push(Solution {
+                year,
+                exclude_pairs,
+                pairs: vec![pair],
+                ..Default::default()
+            });
+        }
+        let found = |input: &Input, pair: Pair<String>| {
+            solve_variety(input, Variety::Variations)
+                .iter()
+                .any(|pairs| pairs.contains(&pair))
+        };
+        assert!(!found(&input, pair("A", "B")));
+        assert!(found(&input, pair("C", "D")));
+
+        input.history_lookback = Some(2);
+        assert!(found(&input, pair("A", "B")));
+        assert!(!found(&input, pair("B", "C")));
+        assert!(!found(&input, pair("C", "D")));
+    }
+
+    #[test]
+    fn preview_only_outgoing() {
+        let input = input(&["Alice", "Bob", "Carol", "Dana"]);
+        let draws = vec![Draw {
+            pairs: vec![
+                Pair::new("Alice".into(), "Bob".into()),
+                Pair::new("Bob".into(), "Carol".into()),
+                Pair::new("Carol".into(), "Dana".into()),
+                Pair::new("Dana".into(), "Alice".into()),
+            ],
+            ..Default::default()
+        }];
+        let msg = preview("Alice", &draws, &input).unwrap();
+        assert_eq!(msg.email, "Alice <alice@email.com>");
+        assert!(msg.body.contains("Secret Santa for Bob."));
+        // Dana gives to Alice, which the preview must not give away.
+        assert!(!msg.body.contains("Dana"));
+        assert!(preview("Eve", &draws, &input).is_none());
+    }
+
+    #[test]
+    fn parse_person() {
+        let p = p0();
+        // assert_eq!(ron::ser::to_string_pretty(&p, PrettyConfig::default()).unwrap(), "");
+        assert_eq!(
+            ron::ser::to_string(&p).unwrap(),
+            "(name:\"First Last\",email:\"name@email.com\")"
+        );
+    }
+}
@@ -1,422 +1,425 @@
-//! # secret-santa: Finds a secret santa solution
-//!
-//! This tool accepts an input file describing a set of people who wish to be
-//! each other's secret santas. The hope is that by using this tool, the person
-//! organizing it can do so blind to who each other's secret santas are.
-//!
-//! Finding a secret santa solution imposes a number of constraints:
-//!
-//! 1. No one may be their own secret santa.
-//!
-//! 2. Each person is a secret santa for only _one_ other person.
-//!
-//! 3. Each person is a recipient for only _one_ other person.
-//!
-//! The above rules feel like a given. The next set of rules are more
-//! discretionary.
-//!
-//! 4. If X is a secret santa to Y, then Y is NOT is a secret santa to X.
-//!
-//! It just seemed like little cycles like this wouldn't be fun. There can be
-//! longer cycles though.
-//!
-//! 5. Optional but we do not permit members of the same household to be each
-//! other's secret santa.
-//!
-//! 6. Optional the history of secret santas can be used to ensure that whomever
-//! you got last year or the year before, you won't get them again. (You can't
-//! go back indefinitely though otherwise there would be no solutions.)
-//!
-//! # Input Sample
-//!
-//! ```
-//! (
-//!     people: [
-//!         (
-//!             name: "John",
-//!             email: "john@email.com",
-//!         ),
-//!         (
-//!             name: "Sean",
-//!             email: "sean@email.com",
-//!         ),
-//!         (
-//!             name: "Shane",
-//!             email: "shane@email.com",
-//!         ),
-//!     ],
-//!     whitelist: [
-//!         (
-//!             giver: "Sean",
-//!             receiver: "Shane",
-//!         ),
-//!     ],
-//!     blacklist: [
-//!         (
-//!             giver: "Sean",
-//!             receiver: "Shane",
-//!         ),
-//!     ],
-//!     blacklist_sets: [
-//!         [
-//!             "John",
-//!             "Sean",
-//!         ],
-//!     ],
-//!     history: [
-//!         (
-//!             year: 2024,
-//!             exclude_pairs: true,
-//!             pairs: [
-//!                 (
-//!                     giver: "John",
-//!                     receiver: "Shane",
-//!                 ),
-//!                 (
-//!                     giver: "Sean",
-//!                     receiver: "John",
-//!                 ),
-//!                 (
-//!                     giver: "Shane",
-//!                     receiver: "Sean",
-//!                 ),
-//!             ],
-//!         ),
-//!     ],
-//! )
-//! ```
-//!
-use clap::Parser;
-use cmd_lib::run_cmd;
-use rand::prelude::IteratorRandom;
+//! The `secret-santa` command: reads an input file, draws a solution with
+//! the library, and delivers the messages.
+use clap::{Parser, Subcommand};
+use rand::Rng;
 use ron::ser::PrettyConfig;
-use satoxid::{
-    constraints::{And, ExactlyK, If, Not, Or},
-    Backend, CadicalEncoder, Encoder, Model,
-};
-use serde::{Deserialize, Serialize};
+use secret_santa::*;
 use std::{
-    cmp::Reverse,
-    fmt::{self, Debug, Write},
-    fs::File,
-    hash::Hash,
-    path::PathBuf,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 #[derive(Parser, Debug)]
+#[command(subcommand_negates_reqs = true)]
 struct Cli {
-    #[arg(long)]
-    write_default: bool,
+    #[command(subcommand)]
+    command: Option<Subcommands>,
+    /// Write a default input file to FILE, or print it if there's no FILE
+    #[arg(long, value_name = "FILE", num_args = 0..=1, value_hint = clap::ValueHint::FilePath)]
+    write_default: Option<Option<PathBuf>>,
+    /// Overwrite the file given to `--write-default` if it exists
+    #[arg(long, requires = "write_default")]
+    force: bool,
     /// Execute a command: cat $body | $exec -s "$subject" "First <name@email.com>"
+    ///
+    /// Overrides the `exec` field of the input file. Repeat it to give backup
+    /// commands, tried in order until one succeeds.
     #[arg(long)]
-    exec: Option<String>,
-    /// Dry run, won't execute command, will echo it: cat $body; echo $exec -s "$subject" "First <name@email.com>"
+    exec: Vec<String>,
+    /// Dry run, won't execute command, will echo it: $exec -s "$subject" "First <name@email.com>".
+    /// With no delivery command, prints every message to proofread instead.
+    /// The pairs, and the messages beside echoed commands, are shown only
+    /// with `--verbose`
     #[arg(long, short = 'n', default_value_t = false)]
     dry_run: bool,
-    /// The path to read
-    #[arg(required = true, value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
-    input: PathBuf,
-}
-
-#[derive(Debug, Clone, Deserialize, Serialize, Default)]
-struct Solution {
-    year: u16,
-    exclude_pairs: bool,
-    pairs: Vec<Pair<String>>,
+    /// Say more about what was done, e.g., show the pairs and messages of a
+    /// dry run, which name the receivers, and log how the draw went.
+    /// `RUST_LOG` overrides the level
+    #[arg(long, short = 'v')]
+    verbose: bool,
+    /// Prefer some solutions over others when choosing one
+    #[arg(long, value_enum)]
+    prefer: Option<Preference>,
+    /// Choose among the solutions with the most pairs never drawn before;
+    /// the same as `--prefer novel-pairs`
+    #[arg(long, conflicts_with = "prefer")]
+    maximize_novelty: bool,
+    /// Draw by this profile's rules instead of the input file's `rules`
+    #[arg(long, value_name = "NAME")]
+    profile: Option<String>,
+    /// The SAT solver to draw with
+    #[arg(long, value_enum, default_value_t = SatSolver::Cadical)]
+    solver: SatSolver,
+    /// How different the solutions to choose from are
+    #[arg(long, value_enum, default_value_t = Variety::Exact)]
+    variety: Variety,
+    /// Prefer the solution that changes the fewest pairs of this prior solution
+    #[arg(long, value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+    stable_from: Option<PathBuf>,
+    /// Report how the assignment closest to this prior solution differs from
+    /// it under the current constraints, and send nothing
+    #[arg(long, value_name = "FILE", conflicts_with = "stable_from", value_hint = clap::ValueHint::FilePath)]
+    what_if: Option<PathBuf>,
+    /// The year of this secret santa, defaults to the current year
+    #[arg(long)]
+    year: Option<u16>,
+    /// Seed the choice among solutions to reproduce a past draw. Overrides
+    /// `SECRET_SANTA_SEED`
+    #[arg(long)]
+    seed: Option<u64>,
+    /// Write the body of the messages from this template instead of the
+    /// usual wording, e.g., "{giver}, you give to {receiver}."
+    #[arg(long, value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+    template: Option<PathBuf>,
+    /// Send email through this SMTP server instead of running `--exec`
+    #[cfg(feature = "smtp")]
+    #[arg(
+        long,
+        value_name = "HOST",
+        env = "SECRET_SANTA_SMTP_SERVER",
+        requires = "smtp_from"
+    )]
+    smtp: Option<String>,
+    /// The SMTP server's port; 465 connects with TLS, others use STARTTLS
+    #[cfg(feature = "smtp")]
+    #[arg(
+        long,
+        value_name = "PORT",
+        env = "SECRET_SANTA_SMTP_PORT",
+        default_value_t = 587
+    )]
+    smtp_port: u16,
+    /// The SMTP username, if the server requires one
+    #[cfg(feature = "smtp")]
+    #[arg(long, value_name = "NAME", env = "SECRET_SANTA_SMTP_USERNAME")]
+    smtp_username: Option<String>,
+    /// The SMTP password; best given in the environment rather than on the
+    /// command line
+    #[cfg(feature = "smtp")]
+    #[arg(
+        long,
+        value_name = "PASSWORD",
+        env = "SECRET_SANTA_SMTP_PASSWORD",
+        hide_env_values = true
+    )]
+    smtp_password: Option<String>,
+    /// Who the email is from, e.g., "Santa <santa@email.com>"
+    #[cfg(feature = "smtp")]
+    #[arg(long, value_name = "ADDRESS", env = "SECRET_SANTA_SMTP_FROM")]
+    smtp_from: Option<String>,
+    /// Deliver to people with this contact method using this command instead
+    /// of `--exec`, e.g., `--contact-exec "sms=bin/sms-gateway"`
+    #[arg(long, value_name = "METHOD=COMMAND", value_parser = parse_contact_exec)]
+    contact_exec: Vec<(String, String)>,
+    /// Send a reminder to confirm their assignment to each person named in
+    /// this file, one per line, instead of drawing
+    #[arg(long, value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+    remind: Option<PathBuf>,
+    /// Probe each person's mail server to catch addresses that don't exist
+    ///
+    /// This is best-effort: many servers accept every address.
+    #[arg(long)]
+    check_emails: bool,
+    /// Only count the valid assignments, without the rules checked at
+    /// selection time, and send nothing. `--max-solutions` caps the count
+    #[arg(long)]
+    count_only: bool,
+    /// Find at most this many solutions to choose among. Fewer is faster for
+    /// large groups but leaves less variety to choose from [default: 100, or
+    /// 10000 with `--count-only`]
+    #[arg(long, value_name = "COUNT")]
+    max_solutions: Option<usize>,
+    /// Print the whitelisted and blacklisted pairs with their reasons, and
+    /// send nothing
+    #[arg(long)]
+    explain: bool,
+    /// Fail unless at least this many independent solutions exist to choose
+    /// from, before the rules checked at selection time
+    #[arg(long, value_name = "COUNT")]
+    require_count: Option<usize>,
+    /// Check that the solution in this file, e.g., a disputed one, is one the
+    /// encoded constraints allow, and send nothing
+    #[arg(long, value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+    check_solution: Option<PathBuf>,
+    /// Print the time spent in each phase of the run
+    #[arg(long)]
+    timings: bool,
+    /// After sending, email this address a digest of who was sent their
+    /// message and who wasn't, but not who they give to
+    #[arg(long, value_name = "ADDRESS", visible_alias = "organizer-email")]
+    organizer_digest: Option<String>,
+    /// List the assignments in the organizer's digest too
+    #[arg(long, requires = "organizer_digest")]
+    include_assignments: bool,
+    /// Sign each message with a token made from the secret in this file so
+    /// recipients can `verify-token` that it came from this run
+    ///
+    /// Anyone with the secret can make tokens, so keep it private and out of
+    /// the input file. Use a new one for each run.
+    #[arg(long, value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+    token_secret: Option<PathBuf>,
+    /// Write a page where each person enters their token to read their own
+    /// message, and send them only their token. Host the page instead of
+    /// emailing the assignments
+    #[arg(long, value_name = "FILE", requires = "token_secret", value_hint = clap::ValueHint::FilePath)]
+    reveal_page: Option<PathBuf>,
+    /// Print the message this person, e.g., the organizer, will be sent. It
+    /// says who they give to but nothing of who gives to them
+    #[arg(long, value_name = "NAME")]
+    preview_self: Option<String>,
+    /// Write a CSV summary of each person's constraints and history to this
+    /// file, and send nothing
+    #[arg(long, value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+    people_report: Option<PathBuf>,
+    /// Also give each giver a fallback receiver in case theirs declines
+    #[arg(long)]
+    with_fallback: bool,
+    /// Send one email listing every assignment for givers who share an address
+    #[arg(long)]
+    combine_shared_emails: bool,
+    /// Save the encoding of the core rules to this file, or reuse it if it's
+    /// there and has the same people. Otherwise it's replaced
+    #[arg(long, value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+    base_encoding: Option<PathBuf>,
+    /// Read the people from this CSV file instead of the input file. It needs
+    /// `name` and `email` columns; any others become attributes
+    #[arg(long, value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+    people: Option<PathBuf>,
+    /// Read the people as CSV from this command's output, e.g., a script that
+    /// queries an LDAP directory
+    #[cfg(feature = "people-exec")]
+    #[arg(long, value_name = "COMMAND", conflicts_with = "people")]
+    people_exec: Option<String>,
+    /// Add the assignment to this RON list of history, replacing any of the
+    /// same year, to paste into next year's input
+    #[arg(long, value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+    save_history: Option<PathBuf>,
+    /// Write the assignment as a graphviz digraph, one color per cycle
+    #[arg(long, value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+    dot: Option<PathBuf>,
+    /// Write the order to open the gifts in at the event, cycle by cycle, so
+    /// the host can reveal them one at a time
+    #[arg(long, value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+    reveal_order: Option<PathBuf>,
+    /// Append a line describing this run, but not its assignments, to a file
+    #[arg(long, value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+    audit_log: Option<PathBuf>,
+    /// The path to read, or `-` to read stdin as RON, or JSON if it starts
+    /// with `{`
+    #[arg(
+        required_unless_present = "write_default",
+        value_name = "FILE",
+        value_hint = clap::ValueHint::FilePath
+    )]
+    input: Option<PathBuf>,
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize, Default)]
-struct Input {
-    people: Vec<Person>,
-    whitelist: Vec<Pair<String>>,
-    blacklist: Vec<Pair<String>>,
-    blacklist_sets: Vec<Vec<String>>,
-    history: Vec<Solution>,
+#[derive(Debug, Subcommand)]
+enum Subcommands {
+    /// Draw a solution, seal it in a file, and print a commitment to it
+    #[command(arg_required_else_help = true)]
+    Commit {
+        /// The path to read
+        #[arg(required = true, value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+        input: PathBuf,
+        /// The path to write the sealed solution
+        #[arg(required = true, value_name = "SEALED", value_hint = clap::ValueHint::FilePath)]
+        output: PathBuf,
+    },
+    /// Check a sealed solution against its commitment and deliver it
+    #[command(arg_required_else_help = true)]
+    Reveal {
+        /// The path to read
+        #[arg(required = true, value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+        input: PathBuf,
+        /// The sealed solution written by `commit`
+        #[arg(required = true, value_name = "SEALED", value_hint = clap::ValueHint::FilePath)]
+        sealed: PathBuf,
+        /// The commitment printed by `commit`
+        #[arg(required = true)]
+        commitment: String,
+    },
+    /// Check that a message's verification token came from this secret
+    #[command(arg_required_else_help = true)]
+    VerifyToken {
+        /// The secret given to `--token-secret`
+        #[arg(long, required = true, value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+        secret: PathBuf,
+        /// Who the message was sent to, e.g., "First Last <name@email.com>"
+        #[arg(required = true)]
+        recipient: String,
+        /// The token at the bottom of the message
+        #[arg(required = true)]
+        token: String,
+    },
+    /// Add a person to the input file, e.g., someone's new partner
+    #[command(arg_required_else_help = true)]
+    AddPerson {
+        /// The path to rewrite
+        #[arg(required = true, value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+        input: PathBuf,
+        #[arg(required = true)]
+        name: String,
+        #[arg(required = true)]
+        email: String,
+        /// Put them in this member's household
+        #[arg(long, value_name = "NAME")]
+        household_of: Option<String>,
+    },
+    /// Check that a solution exists without composing or sending anything
+    #[command(arg_required_else_help = true)]
+    Validate {
+        /// The path to read
+        #[arg(required = true, value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+        input: PathBuf,
+    },
+    /// Print a markdown checklist for the organizer with an attendance list
+    #[command(arg_required_else_help = true)]
+    Checklist {
+        /// The path to read
+        #[arg(required = true, value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+        input: PathBuf,
+    },
+    /// Write an input file with the people of a `name,email` list, one per
+    /// line, or print it if there's no OUTPUT
+    #[command(arg_required_else_help = true)]
+    Generate {
+        /// The list to read
+        #[arg(required = true, value_name = "LIST", value_hint = clap::ValueHint::FilePath)]
+        list: PathBuf,
+        /// The path to write; JSON if it ends in `.json`, otherwise RON
+        #[arg(value_name = "OUTPUT", value_hint = clap::ValueHint::FilePath)]
+        output: Option<PathBuf>,
+        /// Overwrite OUTPUT if it exists
+        #[arg(long, requires = "output")]
+        force: bool,
+    },
 }
 
-impl Input {
-    /// Confirm all names present are in the people list.
-    fn check_history(&self) {
-        for solution in &self.history {
-            for pair in &solution.pairs {
-                if !self.people.iter().any(|p| p.name == pair.giver) {
-                    panic!(
-                        "Giver named '{}' present in history but not found in people set.",
-                        &pair.giver
-                    );
-                }
-
-                if !self.people.iter().any(|p| p.name == pair.receiver) {
-                    panic!(
-                        "Receiver named '{}' present in history but not found in people set.",
-                        &pair.receiver
-                    );
-                }
-            }
+impl From<&Cli> for DrawOptions {
+    fn from(cli: &Cli) -> Self {
+        DrawOptions {
+            variety: cli.variety,
+            prefer: cli
+                .prefer
+                .or(cli.maximize_novelty.then_some(Preference::NovelPairs)),
+            with_fallback: cli.with_fallback,
+            max_solutions: cli.max_solutions.unwrap_or(MAX_SOLUTIONS),
+            require_count: cli.require_count,
+            base_encoding: cli.base_encoding.clone(),
+            solver: cli.solver,
         }
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
-struct Person {
-    name: String,
-    email: String,
+/// Parse a `--contact-exec` value of the form `METHOD=COMMAND`.
+fn parse_contact_exec(s: &str) -> Result<(String, String), String> {
+    let (method, command) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected METHOD=COMMAND but got '{s}'"))?;
+    Ok((method.to_string(), command.to_string()))
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
-struct Pair<T>
-where
-    T: Debug + Eq + Hash + PartialEq + Clone,
-{
-    giver: T,
-    receiver: T,
-}
-
-impl<T> Pair<T>
-where
-    T: Debug + Eq + Hash + PartialEq + Clone,
-{
-    fn new(x: T, y: T) -> Self {
-        Self {
-            giver: x,
-            receiver: y,
-        }
-    }
-}
-
-fn encode_secret_santa_rules<T: Debug + Eq + Hash + PartialEq + Clone>(
-    universe: &[T],
-    encoder: &mut Encoder<Pair<T>, impl Backend>,
-) {
-    let len = universe.len();
-    // Each person is someone's giver.
-    for p in 0..universe.len() {
-        // let lits = (0..len).filter_map(|x| (x != p).then_some(Pair { giver: universe[p].clone(), receiver: universe[x].clone() }));
-        let lits = (0..len).filter_map(|x| {
-            (true).then_some(Pair {
-                giver: universe[p].clone(),
-                receiver: universe[x].clone(),
-            })
+/// Return the SMTP server given on the command line, if any.
+fn smtp(cli: &Cli) -> Option<Smtp> {
+    #[cfg(feature = "smtp")]
+    if let Some(ref server) = cli.smtp {
+        return Some(Smtp {
+            server: server.clone(),
+            port: cli.smtp_port,
+            username: cli.smtp_username.clone(),
+            password: cli.smtp_password.clone(),
+            from: cli.smtp_from.clone().unwrap(),
         });
-        encoder.add_constraint(ExactlyK { k: 1, lits });
     }
-    // Each person is someone's receiver.
-    for p in 0..universe.len() {
-        // let lits = (0..len).filter_map(|x| (x != p).then_some(Pair { giver: universe[x].clone(), receiver: universe[p].clone() }));
-        let lits = (0..len).filter_map(|x| {
-            (true).then_some(Pair {
-                giver: universe[x].clone(),
-                receiver: universe[p].clone(),
-            })
-        });
-        encoder.add_constraint(ExactlyK { k: 1, lits });
-    }
-
-    // No one can give to themselves.
-    let lits = (0..len).map(|p| Pair {
-        giver: universe[p].clone(),
-        receiver: universe[p].clone(),
-    });
-    encoder.add_constraint(Not(Or(lits)));
-
-    // Don't have small cycles.
-    for p in 0..universe.len() {
-        for j in p..universe.len() {
-            encoder.add_constraint(If {
-                cond: Pair {
-                    giver: universe[p].clone(),
-                    receiver: universe[j].clone(),
-                },
-                then: Not(Pair {
-                    giver: universe[j].clone(),
-                    receiver: universe[p].clone(),
-                }),
-            });
-        }
-    }
-}
-
-fn include_pairs<T: Debug + Eq + Hash + PartialEq + Clone>(
-    lits: impl Iterator<Item = Pair<T>> + Clone,
-    encoder: &mut Encoder<Pair<T>, impl Backend>,
-) {
-    encoder.add_constraint(And(lits));
+    #[cfg(not(feature = "smtp"))]
+    let _ = cli;
+    None
 }
 
-fn exclude_pairs<T: Debug + Eq + Hash + PartialEq + Clone>(
-    lits: impl Iterator<Item = Pair<T>> + Clone,
-    encoder: &mut Encoder<Pair<T>, impl Backend>,
-) {
-    encoder.add_constraint(Not(Or(lits)));
-}
-
-#[allow(dead_code)]
-fn exclude_some_pairs<T: Debug + Eq + Hash + PartialEq + Clone>(
-    lits: impl Iterator<Item = Pair<T>> + Clone,
-    encoder: &mut Encoder<Pair<T>, impl Backend>,
-) {
-    encoder.add_constraint(Not(And(lits)));
-}
-
-fn exclude_pairs_symmetric<T: Debug + Eq + Hash + PartialEq + Clone>(
-    lits: impl Iterator<Item = Pair<T>> + Clone,
-    encoder: &mut Encoder<Pair<T>, impl Backend>,
-) {
-    exclude_pairs(lits.clone(), encoder);
-    exclude_pairs(
-        lits.map(
-            |Pair {
-                 giver: a,
-                 receiver: b,
-             }| Pair {
-                giver: b,
-                receiver: a,
-            },
-        ),
-        encoder,
-    );
-}
-
-fn exclude_sets<T: Debug + Eq + Hash + PartialEq + Clone>(
-    people: &[T],
-    encoder: &mut Encoder<Pair<T>, impl Backend>,
-) {
-    let len = people.len();
-    let mut accum = vec![];
-    for x in 0..len {
-        for y in x..len {
-            accum.push(Pair {
-                giver: people[x].clone(),
-                receiver: people[y].clone(),
-            });
-        }
+/// Print what each draw found and kept on the way to its choice.
+fn print_report(draws: &[Draw]) {
+    for line in draws.iter().flat_map(|draw| &draw.report) {
+        println!("{line}");
     }
-    exclude_pairs_symmetric(accum.into_iter(), encoder);
 }
 
-#[derive(Debug)]
-struct Message {
-    subject: String,
-    body: String,
-    email: String,
-}
-
-/// Return the givers for this person.
-fn givers_for<'a>(receiver: &'a str, input: &'a Input) -> impl Iterator<Item = String> + use<'a> {
-    input.history.iter().flat_map(move |x| {
-        x.pairs
-            .iter()
-            .filter(move |p| p.receiver == receiver)
-            .map(|p| format!("{} ({})", p.giver, x.year))
-    })
-}
-
-/// Return the receivers for this person.
-fn receivers_for<'a>(giver: &'a str, input: &'a Input) -> impl Iterator<Item = String> + use<'a> {
-    input.history.iter().flat_map(move |x| {
-        x.pairs
-            .iter()
-            .filter(move |p| p.giver == giver)
-            .map(|p| format!("{} ({})", p.receiver, x.year))
-    })
+/// Print every pair drawn.
+fn print_pairs(draws: &[Draw]) {
+    for pair in draws.iter().flat_map(|draw| &draw.pairs) {
+        println!("{} gives to {}", pair.giver, pair.receiver);
+    }
 }
 
-fn compose_message(pair: &Pair<String>, input: &Input) -> Result<Message, fmt::Error> {
-    let giver = &pair.giver;
-    let receiver = &pair.receiver;
-    let subject = format!("Secret Santa {giver}: Keep it secret! Keep it safe!");
-    let mut body = String::new();
-    writeln!(body, "{giver}, you are the Secret Santa for {receiver}.")?;
-
-    let mut receivers = receivers_for(giver, input).peekable();
-
-    if receivers.peek().is_some() {
-        writeln!(body)?;
-        write!(body, "You were Secret Santa for ")?;
-        write!(body, "{}", receivers.next().unwrap())?;
-        while let Some(receiver) = receivers.next() {
-            if receivers.peek().is_none() {
-                write!(body, ", and {}", receiver)?;
-            } else {
-                write!(body, ", {}", receiver)?;
-            }
-        }
-        writeln!(body, ".")?;
+/// Read the input file, exiting with the error if it can't be used, and warn
+/// if it gives any assignments away.
+fn load_input(
+    cli: &Cli,
+    path: &Path,
+    year: u16,
+    source: Option<&dyn PeopleSource>,
+    timings: &mut Timings,
+) -> (Vec<u8>, Input) {
+    let (bytes, input) = read_input(path, year, source, cli.profile.as_deref(), timings)
+        .unwrap_or_else(|e| {
+            eprintln!("{e}");
+            std::process::exit(1);
+        });
+    if let Some(warning) = input.public_warning() {
+        eprintln!("{warning}");
     }
+    (bytes, input)
+}
 
-    let mut givers = givers_for(giver, input).peekable();
-
-    if givers.peek().is_some() {
-        writeln!(body)?;
-        write!(body, "You had these Secret Santas in Christmases past: ")?;
-        write!(body, "{}", givers.next().unwrap())?;
-        // for giver in givers {
-        //     write!(body, ", {}", giver);
-        // }
-        while let Some(giver) = givers.next() {
-            if givers.peek().is_none() {
-                write!(body, ", and {}", giver)?;
-            } else {
-                write!(body, ", {}", giver)?;
-            }
+/// Use the template given on the command line, if any, for the messages.
+fn use_template(cli: &Cli, input: &mut Input) {
+    if let Some(ref path) = cli.template {
+        let template = std::fs::read_to_string(path).expect("Failed opening template");
+        if let Err(e) = input.set_template(template) {
+            eprintln!("{e}");
+            std::process::exit(1);
         }
-        writeln!(body, ".")?;
     }
-
-    writeln!(
-        body,
-        r#"
-* * *
-Brought to you by secret-santa[1].
-
-[1]: https://github.com/shanecelis/secret-santa
-"#
-    )?;
-
-    let email = input
-        .people
-        .iter()
-        .find(|p| p.name == pair.giver)
-        .expect("Failed to find email address")
-        .email
-        .clone();
-    let name_and_email = format!("{} <{}>", pair.giver, email);
-    Ok(Message {
-        subject,
-        body,
-        email: name_and_email,
-    })
 }
 
-fn extract_pos<V>(model: Model<V>) -> Vec<V>
-where
-    V: Clone,
-    V: Eq,
-    V: Hash,
-    V: Debug,
-{
-    model
-        .vars()
-        .filter_map(|v| v.is_pos().then(|| v.unwrap()))
-        .collect()
+/// Return the people source given on the command line, if any.
+fn people_source(cli: &Cli) -> Option<Box<dyn PeopleSource>> {
+    #[cfg(feature = "people-exec")]
+    if let Some(ref command) = cli.people_exec {
+        return Some(Box::new(ExecPeople {
+            command: command.clone(),
+        }));
+    }
+    cli.people
+        .as_ref()
+        .map(|path| -> Box<dyn PeopleSource> { Box::new(CsvPeople { path: path.clone() }) })
 }
 
 fn main() -> std::io::Result<()> {
     let cli = Cli::parse();
-
-    if cli.write_default {
+    let level = if cli.verbose {
+        "secret_santa=debug"
+    } else {
+        "warn"
+    };
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(level)).init();
+
+    if let Some(ref path) = cli.write_default {
         let mut input = Input::default();
         let a = Person {
             name: String::from("John"),
             email: String::from("john@email.com"),
+            ..Default::default()
         };
         let b = Person {
             name: String::from("Sean"),
             email: String::from("sean@email.com"),
+            ..Default::default()
         };
         let c = Person {
             name: String::from("Shane"),
             email: String::from("shane@email.com"),
+            ..Default::default()
         };
         input.people.push(a.clone());
         input.people.push(b.clone());
@@ -429,7 +432,7 @@ fn main() -> std::io::Result<()> {
             .push(Pair::new(b.name.clone(), c.name.clone()));
         input
             .blacklist
-            .push(Pair::new(b.name.clone(), c.name.clone()));
+            .push(Pair::new(c.name.clone(), a.name.clone()));
         input.history.push(Solution {
             year: 2024,
             exclude_pairs: true,
@@ -438,153 +441,442 @@ fn main() -> std::io::Result<()> {
                 Pair::new(b.name.clone(), a.name.clone()),
                 Pair::new(c.name.clone(), b.name.clone()),
             ],
+            ..Default::default()
         });
-        // TODO: Should use a stream here.
-        println!(
-            "{}",
-            ron::ser::to_string_pretty(&input, PrettyConfig::default()).unwrap()
-        );
+        let contents = ron::ser::to_string_pretty(&input, PrettyConfig::default()).unwrap();
+        match path {
+            // TODO: Should use a stream here.
+            None => println!("{}", contents),
+            Some(path) => {
+                if let Err(e) = write_new(path, &contents, cli.force) {
+                    eprintln!("{e}");
+                    std::process::exit(1);
+                }
+            }
+        }
         return Ok(());
     }
 
-    let f = File::open(cli.input).expect("Failed opening");
+    let year = cli.year.unwrap_or_else(this_year);
+    let prior: Option<Solution> = cli
+        .stable_from
+        .as_ref()
+        .or(cli.what_if.as_ref())
+        .map(|path| {
+            let bytes = std::fs::read(path).expect("Failed opening prior solution");
+            ron::de::from_bytes(&bytes).expect("Failed parsing prior solution")
+        });
+    let seed = match cli.seed {
+        Some(seed) => Some(seed),
+        None => env_seed().unwrap_or_else(|e| {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }),
+    };
+    let mut selector = RandomSelector(seeded_rng(seed));
+    let source = people_source(&cli);
+    let mut timings = Timings::default();
+
+    match cli.command {
+        Some(Subcommands::Commit {
+            ref input,
+            ref output,
+        }) => {
+            let (_, input) = load_input(&cli, input, year, source.as_deref(), &mut timings);
+            let mut audit = AuditEntry::default();
+            let draws = draw(
+                &input,
+                &DrawOptions::from(&cli),
+                prior.as_ref(),
+                &mut selector,
+                &mut audit,
+                &mut timings,
+            )
+            .unwrap_or_else(|e| {
+                eprintln!("{e}");
+                std::process::exit(1);
+            });
+            print_report(&draws);
+            // Never seeded, so the nonce can't be guessed.
+            let nonce: [u8; 16] = rand::thread_rng().gen();
+            let sealed = Sealed {
+                nonce: nonce.iter().map(|b| format!("{:02x}", b)).collect(),
+                year,
+                draws,
+            };
+            let (contents, commitment) = seal(&sealed);
+            std::fs::write(output, contents)?;
+            println!("Commitment: {commitment}");
+            return Ok(());
+        }
+        Some(Subcommands::Reveal {
+            ref input,
+            ref sealed,
+            ref commitment,
+        }) => {
+            let (_, mut input) = load_input(&cli, input, year, source.as_deref(), &mut timings);
+            use_template(&cli, &mut input);
+            let contents = std::fs::read_to_string(sealed).expect("Failed opening sealed");
+            let sealed = unseal(&contents, commitment).unwrap_or_else(|e| {
+                eprintln!("{e}");
+                std::process::exit(1);
+            });
+            let delivery = Delivery {
+                exec: delivery_commands(&cli.exec, &input),
+                contact_exec: &cli.contact_exec,
+                smtp: smtp(&cli),
+                dry_run: cli.dry_run,
+                verbose: cli.verbose,
+            };
+            if cli.dry_run && cli.verbose {
+                print_pairs(&sealed.draws);
+            }
+            let mut msgs = compose_messages(&sealed.draws, &input);
+            if let Some(ref path) = cli.token_secret {
+                add_tokens(&mut msgs, &read_secret(path));
+            }
+            if cli.combine_shared_emails {
+                msgs = delivery.combine(msgs);
+            }
+            return delivery.deliver(msgs);
+        }
+        Some(Subcommands::VerifyToken {
+            ref secret,
+            ref recipient,
+            ref token,
+        }) => {
+            if verify_token(&read_secret(secret), recipient, token) {
+                println!("Valid token.");
+                return Ok(());
+            }
+            eprintln!("Invalid token!");
+            std::process::exit(1);
+        }
+        Some(Subcommands::AddPerson {
+            ref input,
+            ref name,
+            ref email,
+            ref household_of,
+        }) => {
+            let bytes = std::fs::read(input).expect("Failed opening");
+            let mut contents = parse_input(input, &bytes).unwrap_or_else(|e| {
+                eprintln!("{e}");
+                std::process::exit(1);
+            });
+            let person = Person {
+                name: name.clone(),
+                email: email.clone(),
+                ..Default::default()
+            };
+            if let Err(e) = contents.add_person(person, household_of.as_deref()) {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+            return std::fs::write(input, serialize_input(input, &contents));
+        }
+        Some(Subcommands::Generate {
+            ref list,
+            ref output,
+            force,
+        }) => {
+            let list = std::fs::read_to_string(list).expect("Failed opening list");
+            let input = skeleton_input(&list).unwrap_or_else(|e| {
+                eprintln!("{e}");
+                std::process::exit(1);
+            });
+            match output {
+                None => println!("{}", serialize_input(Path::new("out.ron"), &input)),
+                Some(path) => {
+                    if let Err(e) = write_new(path, &serialize_input(path, &input), force) {
+                        eprintln!("{e}");
+                        std::process::exit(1);
+                    }
+                }
+            }
+            return Ok(());
+        }
+        Some(Subcommands::Checklist { ref input }) => {
+            let (_, input) = load_input(&cli, input, year, source.as_deref(), &mut timings);
+            print!("{}", checklist(&input));
+            return Ok(());
+        }
+        Some(Subcommands::Validate { ref input }) => {
+            let (_, input) = load_input(&cli, input, year, source.as_deref(), &mut timings);
+            let mut feasible = true;
+            for (label, count) in feasibility(&input, &mut timings) {
+                let label = label.map(|l| format!(" for '{l}'")).unwrap_or_default();
+                if count == 0 {
+                    feasible = false;
+                    println!("No solution exists{label}.");
+                } else {
+                    println!("A solution exists{label}; found {count} independent solutions.");
+                }
+            }
+            if cli.timings {
+                println!("{timings}");
+            }
+            if !feasible {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        None => {}
+    }
 
-    let mut input: Input = ron::de::from_reader(f).expect("Failed parsing");
-    input.check_history();
+    let (bytes, mut input) = load_input(
+        &cli,
+        cli.input.as_ref().unwrap(),
+        year,
+        source.as_deref(),
+        &mut timings,
+    );
+    use_template(&cli, &mut input);
+
+    let delivery = Delivery {
+        exec: delivery_commands(&cli.exec, &input),
+        contact_exec: &cli.contact_exec,
+        smtp: smtp(&cli),
+        dry_run: cli.dry_run,
+        verbose: cli.verbose,
+    };
+
+    if let Some(ref path) = cli.people_report {
+        let file = std::fs::File::create(path)?;
+        people_report(&input, file).map_err(std::io::Error::other)?;
+        return Ok(());
+    }
 
-    let mut encoder = CadicalEncoder::new();
-    input.history.sort_by_key(|sol| Reverse(sol.year));
-    let names: Vec<String> = input.people.iter().map(|p| p.name.clone()).collect();
-    encode_secret_santa_rules(&names, &mut encoder);
-    for blacklist_set in &input.blacklist_sets {
-        exclude_sets(blacklist_set, &mut encoder);
+    if cli.explain {
+        print!("{}", explain(&input));
+        return Ok(());
     }
-    exclude_pairs(input.blacklist.iter().cloned(), &mut encoder);
-    include_pairs(input.whitelist.iter().cloned(), &mut encoder);
 
-    // Exclude historical pairs.
-    for solution in &input.history {
-        if !solution.exclude_pairs {
-            continue;
+    if cli.count_only {
+        let cap = cli.max_solutions.unwrap_or(COUNT_CAP);
+        for (label, draw) in input.draws() {
+            let label = label.map(|l| format!(" for '{l}'")).unwrap_or_default();
+            match count_solutions(&draw, cap) {
+                (count, true) => println!("Exactly {count} solutions{label}."),
+                (count, false) => {
+                    println!(
+                        "At least {count} solutions{label}; stopped counting at the cap of {cap}."
+                    )
+                }
+            }
         }
-        exclude_pairs(solution.pairs.iter().cloned(), &mut encoder);
+        return Ok(());
     }
 
-    let mut solutions = vec![];
-
-    for _ in 0..100 {
-        if let Some(model) = encoder.solve() {
-            let pairs: Vec<Pair<String>> = extract_pos(model);
-            // Two different kinds of exclusions can be done to find multiple
-            // solutions:
-            //
-            // 1) This excludes_some_pairs ensures you can't repeat the same
-            //    thing but variations are allowed.
-            //
-            // ```
-            // exclude_some_pairs(pairs.iter().cloned(), &mut encoder);
-            // ```
-            //
-            // 2) This exlude_pairs ensures none of the pairings found are repeated.
-            //
-            // ```
-            // exclude_pairs(pairs.iter().cloned(), &mut encoder);
-            // ````
-            //
-            // We're doing #2 to ensure variety when choosing a random one.
-            exclude_pairs(pairs.iter().cloned(), &mut encoder);
-            solutions.push(pairs);
+    if let Some(ref path) = cli.check_solution {
+        let bytes = std::fs::read(path).expect("Failed opening solution");
+        let solution: Solution = ron::de::from_bytes(&bytes).expect("Failed parsing solution");
+        if let Err(e) = check_solution(&input, &solution) {
+            eprintln!("{e}");
+            std::process::exit(1);
         }
+        println!("This solution is consistent with the constraints.");
+        return Ok(());
     }
 
-    if solutions.is_empty() {
-        eprintln!("No secret santa solutions found!");
-        std::process::exit(1);
+    if let Some(ref path) = cli.remind {
+        let names: Vec<String> = std::fs::read_to_string(path)
+            .expect("Failed opening reminder list")
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(String::from)
+            .collect();
+        let msgs = compose_reminders(&names, &input);
+        println!("Reminding {} people.", msgs.len());
+        return delivery.deliver(msgs);
     }
 
-    println!(
-        "Found {} independent solutions. Choosing one.",
-        solutions.len()
-    );
-
-    let mut rng = rand::thread_rng();
-    let mut pairs = solutions.swap_remove((0..solutions.len()).choose(&mut rng).unwrap());
-
-    pairs.sort_by(|a, b| a.giver.cmp(&b.giver));
-    let mut msgs = vec![];
-    // Generate all the messages first to confirm there aren't any errors.
-    for pair in &pairs {
+    if cli.check_emails {
         if cli.dry_run {
-            println!("{:?}", pair);
+            for person in &input.people {
+                println!("Would probe {} <{}>", person.name, person.email);
+            }
+        } else {
+            let mut probe = SmtpProbe {
+                timeout: Duration::from_secs(10),
+            };
+            let mut rejected = false;
+            for (person, result) in check_emails(&input.people, &mut probe) {
+                match result {
+                    ProbeResult::Rejected(reply) => {
+                        rejected = true;
+                        eprintln!(
+                            "Likely bad address for {}: {} ({})",
+                            person.name, person.email, reply
+                        );
+                    }
+                    ProbeResult::Unknown(reply) => eprintln!(
+                        "Could not check address for {}: {} ({})",
+                        person.name, person.email, reply
+                    ),
+                    ProbeResult::Accepted => {}
+                }
+            }
+            if rejected {
+                std::process::exit(1);
+            }
         }
-        let msg = compose_message(pair, &input).expect("Failed to compose message");
-        msgs.push(msg);
     }
 
-    for msg in msgs {
-        let subject = msg.subject;
-        let body = msg.body;
-        let email = msg.email;
-
-        if let Some(ref exec) = cli.exec {
-            let exec_args: Vec<&str> = exec.split_whitespace().collect();
-
-            if cli.dry_run {
-                run_cmd!(echo $body | cat; echo $[exec_args] -s $subject $email)?;
-            } else {
-                run_cmd!(echo $body | $[exec_args] -s $subject $email)?;
+    let mut audit = AuditEntry {
+        timestamp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Clock is before 1970")
+            .as_secs(),
+        input_hash: sha256_hex(&bytes),
+        people: input.people.len(),
+        seed,
+        delivery: match (delivery.exec.is_empty(), cli.dry_run) {
+            (_, true) => "dry-run",
+            _ if delivery.smtp.is_some() => "smtp",
+            (true, false) => "none",
+            (false, false) => "exec",
+        },
+        ..Default::default()
+    };
+
+    let draws = match draw(
+        &input,
+        &DrawOptions::from(&cli),
+        prior.as_ref(),
+        &mut selector,
+        &mut audit,
+        &mut timings,
+    ) {
+        Ok(draws) => draws,
+        Err(e) => {
+            eprintln!("{e}");
+            if let Some(ref path) = cli.audit_log {
+                audit.outcome = String::from("no-solutions");
+                append_audit(path, &audit)?;
             }
+            std::process::exit(1);
         }
+    };
+    print_report(&draws);
+    if cli.what_if.is_some() {
+        let pairs: Vec<Pair<String>> = draws.iter().flat_map(|draw| draw.pairs.clone()).collect();
+        print!("{}", solution_diff(&prior.unwrap().pairs, &pairs));
+        return Ok(());
+    }
+    if let Some(ref path) = cli.dot {
+        std::fs::write(path, to_dot(&draws))?;
+    }
+    if let Some(ref path) = cli.reveal_order {
+        std::fs::write(path, reveal_order(&draws))?;
+    }
+    if let Some(ref path) = cli.save_history {
+        save_history(path, year, &draws)?;
     }
-    Ok(())
+    if let Some(ref name) = cli.preview_self {
+        match preview(name, &draws, &input) {
+            Some(msg) => println!(
+                "To: {}\nSubject: {}\n\n{}",
+                msg.email, msg.subject, msg.body
+            ),
+            None => eprintln!("Warning: '{name}' gives no gift to preview."),
+        }
+    }
+    let start = Instant::now();
+    if cli.dry_run && cli.verbose {
+        print_pairs(&draws);
+    }
+    let mut msgs = compose_messages(&draws, &input);
+    if let Some(ref path) = cli.reveal_page {
+        let secret = read_secret(cli.token_secret.as_ref().unwrap());
+        std::fs::write(path, reveal_page(&msgs, &secret))?;
+        msgs = msgs
+            .iter()
+            .map(|msg| token_message(msg, &secret, &input.footer()))
+            .collect();
+    } else if let Some(ref path) = cli.token_secret {
+        add_tokens(&mut msgs, &read_secret(path));
+    }
+    if cli.combine_shared_emails {
+        msgs = delivery.combine(msgs);
+    }
+    timings.add("composition", start);
+    if cli.dry_run && delivery.exec.is_empty() && delivery.smtp.is_none() {
+        for msg in &msgs {
+            print!("{}", dry_run_message(msg));
+        }
+        println!("{}", dry_run_summary(&msgs, cli.verbose));
+    }
+
+    let start = Instant::now();
+    let result = match cli.organizer_digest {
+        Some(ref organizer) => {
+            let statuses: Vec<(String, Result<(), String>)> = msgs
+                .iter()
+                .map(|msg| {
+                    let status = delivery.deliver_one(msg).map_err(|e| e.to_string());
+                    (msg.email.clone(), status)
+                })
+                .collect();
+            let assignments = cli.include_assignments.then_some(draws.as_slice());
+            let digest = compose_digest(organizer, &statuses, assignments, &input.footer());
+            let failed = statuses
+                .iter()
+                .filter(|(_, status)| status.is_err())
+                .count();
+            delivery.deliver_one(&digest).and(match failed {
+                0 => Ok(()),
+                _ => Err(std::io::Error::other(format!(
+                    "Failed sending {failed} messages."
+                ))),
+            })
+        }
+        None => delivery.deliver(msgs),
+    };
+    timings.add("sending", start);
+    if cli.timings {
+        println!("{timings}");
+    }
+    if let Some(ref path) = cli.audit_log {
+        audit.outcome = match result {
+            Ok(()) => String::from("ok"),
+            Err(ref e) => format!("{:?}", e.to_string()),
+        };
+        append_audit(path, &audit)?;
+    }
+    result
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
 
-    fn p0() -> Person {
-        Person {
-            name: String::from("First Last"),
-            email: String::from("name@email.com"),
-        }
+    #[test]
+    fn maximize_novelty_prefers_novel_pairs() {
+        let cli = Cli::parse_from(["secret-santa", "--maximize-novelty", "in.ron"]);
+        assert_eq!(DrawOptions::from(&cli).prefer, Some(Preference::NovelPairs));
+        let cli = Cli::parse_from(["secret-santa", "in.ron"]);
+        assert_eq!(DrawOptions::from(&cli).prefer, None);
     }
 
     #[test]
-    fn parse_person() {
-        let p = p0();
-        // assert_eq!(ron::ser::to_string_pretty(&p, PrettyConfig::default()).unwrap(), "");
-        assert_eq!(
-            ron::ser::to_string(&p).unwrap(),
-            "(name:\"First Last\",email:\"name@email.com\")"
-        );
+    fn organizer_email_is_digest() {
+        let cli = Cli::parse_from([
+            "secret-santa",
+            "--organizer-email",
+            "santa@email.com",
+            "in.ron",
+        ]);
+        assert_eq!(cli.organizer_digest.as_deref(), Some("santa@email.com"));
     }
-}
 
-// TODO: Add these subcommands next year.
-//
-// #[derive(Debug, Subcommand)]
-// enum Subcommands {
-//     /// Write creature to file path
-//     #[command(arg_required_else_help = true)]
-//     Generate {
-//         #[arg(required = true, value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
-//         input: PathBuf,
-//         /// The path to write
-//         #[arg(required = true, value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
-//         output: PathBuf,
-//     },
-//     Execute {
-//         #[arg(required = true, value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
-//         input: PathBuf,
-//         /// The path to write
-//         #[arg(required = true, value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
-//         output: PathBuf,
-//     },
-//     #[command(arg_required_else_help = true)]
-//     Read {
-//         /// The path to read
-//         #[arg(required = true, value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
-//         path: PathBuf,
-//     },
-// }
+    #[test]
+    fn seed_flag_reproduces() {
+        let cli = Cli::parse_from(["secret-santa", "--seed", "7", "in.ron"]);
+        let solutions: Vec<Vec<Pair<String>>> = (0..10)
+            .map(|i| vec![Pair::new(i.to_string(), String::from("A"))])
+            .collect();
+        let select = || RandomSelector(seeded_rng(cli.seed)).select(&solutions);
+        assert_eq!(select(), select());
+    }
+}
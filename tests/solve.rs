@@ -0,0 +1,44 @@
+use secret_santa::{solutions, solve, verify_solution, Input, Pair, Person};
+
+fn people(names: &[&str]) -> Vec<Person> {
+    names
+        .iter()
+        .map(|name| Person {
+            name: name.to_string(),
+            email: format!("{}@email.com", name.to_lowercase()),
+            ..Default::default()
+        })
+        .collect()
+}
+
+#[test]
+fn solve_from_another_crate() {
+    let input = Input {
+        people: people(&["A", "B", "C", "D"]),
+        blacklist: vec![Pair::new("A".into(), "B".into())],
+        ..Default::default()
+    };
+    let solutions = solve(&input);
+    assert!(!solutions.is_empty());
+    for pairs in &solutions {
+        assert_eq!(pairs.len(), 4);
+        assert!(!pairs.contains(&Pair::new("A".into(), "B".into())));
+    }
+}
+
+#[test]
+fn first_solutions_lazily() {
+    let names = ["A", "B", "C", "D"].map(String::from);
+    let input = Input {
+        people: people(&["A", "B", "C", "D"]),
+        ..Default::default()
+    };
+    let first: Vec<Vec<Pair<String>>> = solutions(&input).take(3).collect();
+    assert_eq!(first.len(), 3);
+    for (i, pairs) in first.iter().enumerate() {
+        assert_eq!(verify_solution(&names, 1, pairs), Ok(()));
+        for other in &first[i + 1..] {
+            assert!(pairs.iter().any(|pair| !other.contains(pair)));
+        }
+    }
+}